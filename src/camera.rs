@@ -1,44 +1,101 @@
-use nalgebra::{Matrix4, Point3, Vector3};
+use nalgebra::{Matrix4, Point3, Point4, Vector2, Vector3, Vector4};
 
-use crate::transformable::Transformable;
+use crate::{
+    mesh::triangle::Triangle, renderer_config::DepthEncoding, transformable::Transformable,
+};
 
 pub struct Camera {
     position: Point3<f32>,
+    z_near: f32,
+    z_far: f32,
+    orthographic: bool,
+    depth_encoding: DepthEncoding,
     projection_matrix: Matrix4<f32>,
     view_matrix: Matrix4<f32>,
     view_projection_matrix: Matrix4<f32>,
+    inverse_view_projection_matrix: Matrix4<f32>,
 }
 
 impl Camera {
-    ///
+    /// Create a perspective camera with a vertical field of view in radians
     pub fn new(aspect_ratio: f32, field_of_view: f32, z_near: f32, z_far: f32) -> Camera {
         let projection =
             Matrix4::<f32>::new_perspective(aspect_ratio, field_of_view, z_near, z_far);
 
         let mut camera = Camera {
             position: Point3::<f32>::origin(),
+            z_near,
+            z_far,
+            orthographic: false,
+            depth_encoding: DepthEncoding::ReversedZ,
             projection_matrix: projection,
             view_matrix: Matrix4::<f32>::identity(),
             view_projection_matrix: Matrix4::<f32>::identity(),
+            inverse_view_projection_matrix: Matrix4::<f32>::identity(),
         };
 
         camera.update_camera();
         camera
     }
 
-    ///
+    /// Create an orthographic camera viewing a `width` by `height` region
+    pub fn new_orthographic(width: f32, height: f32, z_near: f32, z_far: f32) -> Camera {
+        let projection = Matrix4::<f32>::new_orthographic(
+            -width / 2.0,
+            width / 2.0,
+            -height / 2.0,
+            height / 2.0,
+            z_near,
+            z_far,
+        );
+
+        let mut camera = Camera {
+            position: Point3::<f32>::origin(),
+            z_near,
+            z_far,
+            orthographic: true,
+            depth_encoding: DepthEncoding::ReversedZ,
+            projection_matrix: projection,
+            view_matrix: Matrix4::<f32>::identity(),
+            view_projection_matrix: Matrix4::<f32>::identity(),
+            inverse_view_projection_matrix: Matrix4::<f32>::identity(),
+        };
+
+        camera.update_camera();
+        camera
+    }
+
+    /// Get the position of the camera in world-space
     #[inline]
     pub fn get_position(&self) -> &Point3<f32> {
         &self.position
     }
 
-    ///
+    /// Move the camera to `position`, keeping its orientation
     pub fn set_position(&mut self, position: &Point3<f32>) {
         self.position = *position;
         self.update_camera();
     }
 
-    ///
+    /// Get the mapping from view depth to the values written to the depth buffer
+    #[inline]
+    pub fn get_depth_encoding(&self) -> DepthEncoding {
+        self.depth_encoding
+    }
+
+    /// Set the mapping from view depth to the values written to the depth buffer
+    pub fn set_depth_encoding(&mut self, depth_encoding: DepthEncoding) {
+        self.depth_encoding = depth_encoding;
+    }
+
+    /// Check whether screen-space depths are reciprocals of the stored depth, which happens when
+    /// linear depth has to be interpolated with perspective
+    #[inline]
+    pub fn has_reciprocal_depth(&self) -> bool {
+        self.depth_encoding == DepthEncoding::Linear && !self.orthographic
+    }
+
+    /// Get the world-space direction the camera is looking in
     pub fn get_direction(&self) -> Vector3<f32> {
         -self
             .view_matrix
@@ -47,32 +104,238 @@ impl Camera {
             .transform_vector(&Vector3::<f32>::z_axis())
     }
 
-    ///
+    /// Get the transform from world-space to view-space
+    #[inline]
+    pub fn get_view_matrix(&self) -> &Matrix4<f32> {
+        &self.view_matrix
+    }
+
+    /// Get the transform from world-space to clip-space
     #[inline]
     pub fn get_view_projection_matrix(&self) -> &Matrix4<f32> {
         &self.view_projection_matrix
     }
 
-    ///
+    /// Turn the camera to look at `target`
     pub fn look_at(&mut self, target: &Point3<f32>) {
-        self.view_matrix =
-            Matrix4::<f32>::look_at_lh(&self.position, target, &Vector3::<f32>::z_axis());
+        // Fall back to the Y axis as up when looking straight along the Z axis
+        let direction = target - self.position;
+        let up = if direction.cross(&Vector3::<f32>::z_axis()).norm_squared() > f32::EPSILON {
+            Vector3::<f32>::z_axis()
+        } else {
+            Vector3::<f32>::y_axis()
+        };
+
+        self.view_matrix = Matrix4::<f32>::look_at_lh(&self.position, target, &up);
         self.update_camera();
     }
 
-    ///
+    /// Convert from World to Screen coordinate system, with depth in the camera's depth encoding
+    /// and keeping the reciprocal of clip-space w
+    #[inline]
+    pub fn to_screen(&self, p: Point3<f32>, width: u32, height: u32) -> Point4<f32> {
+        let clip = self.view_projection_matrix * p.to_homogeneous();
+        let transformed = clip.xyz() / clip.w;
+
+        Point4::<f32>::new(
+            ((transformed.x + 1.0) / 2.0) * (width as f32),
+            ((transformed.y + 1.0) / 2.0) * (height as f32),
+            self.encode_depth(self.view_depth(p)),
+            1.0 / clip.w,
+        )
+    }
+
+    /// Convert from Screen to World coordinate system, at a view-space depth along the ray through
+    /// the screen-space position
+    pub fn to_world(
+        &self,
+        x: f32,
+        y: f32,
+        view_depth: f32,
+        width: u32,
+        height: u32,
+    ) -> Point3<f32> {
+        let ndc = Vector2::<f32>::new(2.0 * x / width as f32 - 1.0, 2.0 * y / height as f32 - 1.0);
+        self.ray_point(ndc, view_depth)
+    }
+
+    /// Check whether the front of a surface with `normal` at `p` faces the camera
+    pub fn is_facing(&self, p: &Point3<f32>, normal: &Vector3<f32>) -> bool {
+        let direction = if self.orthographic {
+            let centre = Vector2::<f32>::zeros();
+            self.ray_point(centre, 1.0) - self.ray_point(centre, 0.0)
+        } else {
+            p - self.position
+        };
+        direction.dot(normal) < 0.0
+    }
+
+    /// Get the distance of a point in front of the camera, along the view direction
+    #[inline]
+    pub fn view_depth(&self, p: Point3<f32>) -> f32 {
+        // The view looks along +Z, where perspective projection sets w to -Z
+        if self.orthographic {
+            (self.view_matrix * p.to_homogeneous()).z
+        } else {
+            -(self.view_projection_matrix * p.to_homogeneous()).w
+        }
+    }
+
+    /// Get the corners of the near plane followed by the corners of the far plane, each in order
+    /// around the plane
+    pub fn get_frustum_corners(&self) -> [Point3<f32>; 8] {
+        let corners = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)];
+        let mut points = [Point3::<f32>::origin(); 8];
+        for (i, (x, y)) in corners.iter().enumerate() {
+            let ndc = Vector2::<f32>::new(*x, *y);
+            points[i] = self.ray_point(ndc, self.z_near);
+            points[i + 4] = self.ray_point(ndc, self.z_far);
+        }
+        points
+    }
+
+    /// Check whether a point is between the near and far planes
+    #[inline]
+    pub fn is_within_depth_range(&self, p: &Point3<f32>) -> bool {
+        let depth = self.view_depth(*p);
+        depth >= self.z_near && depth <= self.z_far
+    }
+
+    /// Clip a line segment to the near and far planes, or return `None` if none of it is between
+    /// them
+    pub fn clip_line(
+        &self,
+        p0: Point3<f32>,
+        p1: Point3<f32>,
+    ) -> Option<(Point3<f32>, Point3<f32>)> {
+        let (d0, d1) = (self.view_depth(p0), self.view_depth(p1));
+        let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+
+        // Distances inside each plane are positive
+        for (e0, e1) in [
+            (d0 - self.z_near, d1 - self.z_near),
+            (self.z_far - d0, self.z_far - d1),
+        ] {
+            if e0 < 0.0 && e1 < 0.0 {
+                return None;
+            }
+            if e0 < 0.0 {
+                t0 = t0.max(e0 / (e0 - e1));
+            } else if e1 < 0.0 {
+                t1 = t1.min(e0 / (e0 - e1));
+            }
+        }
+
+        if t0 > t1 {
+            return None;
+        }
+        Some((p0 + (p1 - p0) * t0, p0 + (p1 - p0) * t1))
+    }
+
+    /// Clip a triangle to the near and far planes, returning the triangles fanned out from the
+    /// clipped polygon, or none if none of it is between them
+    pub fn clip_triangle(&self, tri: &Triangle) -> Vec<Triangle> {
+        let mut polygon = vec![tri.a.clone(), tri.b.clone(), tri.c.clone()];
+
+        // Distances inside each plane are positive
+        let planes: [&dyn Fn(f32) -> f32; 2] =
+            [&|depth| depth - self.z_near, &|depth| self.z_far - depth];
+        for distance in planes {
+            let distances: Vec<f32> = polygon
+                .iter()
+                .map(|v| distance(self.view_depth(v.position)))
+                .collect();
+
+            let mut clipped = Vec::with_capacity(polygon.len() + 1);
+            for i in 0..polygon.len() {
+                let j = (i + 1) % polygon.len();
+                let (d0, d1) = (distances[i], distances[j]);
+                if d0 >= 0.0 {
+                    clipped.push(polygon[i].clone());
+                }
+                if (d0 >= 0.0) != (d1 >= 0.0) {
+                    clipped.push(polygon[i].lerp(&polygon[j], d0 / (d0 - d1)));
+                }
+            }
+
+            polygon = clipped;
+            if polygon.len() < 3 {
+                return Vec::new();
+            }
+        }
+
+        (1..polygon.len() - 1)
+            .map(|i| Triangle {
+                a: polygon[0].clone(),
+                b: polygon[i].clone(),
+                c: polygon[i + 1].clone(),
+                colour: tri.colour,
+                material: tri.material,
+            })
+            .collect()
+    }
+
+    /// Convert a view-space depth to a value which is affine in screen-space. Standard depth runs
+    /// from 0 at the near plane to 1 at the far plane, and reversed-Z from 1 to 0. Linear depth is
+    /// stored in view-space units
+    pub fn encode_depth(&self, view_depth: f32) -> f32 {
+        let (n, f) = (self.z_near, self.z_far);
+
+        match (self.depth_encoding, self.orthographic) {
+            (DepthEncoding::Standard, false) => f * (view_depth - n) / (view_depth * (f - n)),
+            (DepthEncoding::ReversedZ, false) => n * (f - view_depth) / (view_depth * (f - n)),
+            (DepthEncoding::Linear, false) => 1.0 / view_depth,
+            (DepthEncoding::Standard, true) => (view_depth - n) / (f - n),
+            (DepthEncoding::ReversedZ, true) => (f - view_depth) / (f - n),
+            (DepthEncoding::Linear, true) => view_depth,
+        }
+    }
+
+    /// Convert a stored depth back to a view-space depth
+    pub fn linearize_depth(&self, depth: f32) -> f32 {
+        let (n, f) = (self.z_near, self.z_far);
+
+        match (self.depth_encoding, self.orthographic) {
+            (DepthEncoding::Standard, false) => f * n / (f - depth * (f - n)),
+            (DepthEncoding::ReversedZ, false) => f * n / (n + depth * (f - n)),
+            (DepthEncoding::Standard, true) => n + depth * (f - n),
+            (DepthEncoding::ReversedZ, true) => f - depth * (f - n),
+            (DepthEncoding::Linear, _) => depth,
+        }
+    }
+
+    /// Get the point at a view-space depth along the ray through a position in normalised device
+    /// coordinates
+    fn ray_point(&self, ndc: Vector2<f32>, view_depth: f32) -> Point3<f32> {
+        let near = self.unproject(ndc, -1.0);
+        let far = self.unproject(ndc, 1.0);
+
+        // View-space depth is affine along the ray
+        let (near_depth, far_depth) = (self.view_depth(near), self.view_depth(far));
+        near + (far - near) * ((view_depth - near_depth) / (far_depth - near_depth))
+    }
+
+    /// Convert a position in normalised device coordinates to World coordinates
+    fn unproject(&self, ndc: Vector2<f32>, z: f32) -> Point3<f32> {
+        let p = self.inverse_view_projection_matrix * Vector4::<f32>::new(ndc.x, ndc.y, z, 1.0);
+        Point3::<f32>::from(p.xyz() / p.w)
+    }
+
+    /// Recompute the combined matrices after the view or projection changes
     fn update_camera(&mut self) {
         self.view_projection_matrix = self.projection_matrix * self.view_matrix;
+        self.inverse_view_projection_matrix = self
+            .view_projection_matrix
+            .try_inverse()
+            .unwrap_or_else(Matrix4::<f32>::identity);
     }
 }
 
 impl Transformable for Camera {
-    ///
     fn translate(&mut self, delta: Vector3<f32>) {
         self.view_matrix *= Matrix4::<f32>::new_translation(&delta); // TODO Test
     }
 
-    ///
     fn rotate(&mut self, x: f32, y: f32, z: f32) {
         let rot_x = Vector3::x() * x;
         let rot_y = Vector3::y() * y;
@@ -81,8 +344,88 @@ impl Transformable for Camera {
         // TODO Test
     }
 
-    ///
     fn scale(&mut self, _: f32) {
         self.update_camera();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex::Vertex;
+    use image::Rgba;
+
+    /// Create a vertex whose texture coordinates follow its x and y
+    fn vertex(x: f32, y: f32, z: f32) -> Vertex {
+        Vertex {
+            position: Point3::<f32>::new(x, y, z),
+            normal: None,
+            tangent: None,
+            uv: Some(Vector2::<f32>::new(x, y)),
+            colour: None,
+        }
+    }
+
+    /// Create a camera at the origin looking down the negative z axis, with planes at 1 and 100
+    fn camera() -> Camera {
+        let mut camera = Camera::new(1.0, std::f32::consts::FRAC_PI_2, 1.0, 100.0);
+        camera.set_position(&Point3::<f32>::origin());
+        camera.look_at(&Point3::<f32>::new(0.0, 0.0, -1.0));
+        camera
+    }
+
+    #[test]
+    fn clipped_triangles_are_between_the_planes() {
+        let camera = camera();
+        let triangle = |a, b, c| Triangle {
+            a,
+            b,
+            c,
+            colour: Rgba([255, 255, 255, 255]),
+            material: 0,
+        };
+
+        // One vertex behind the camera, leaving a quad in front of the near plane
+        let crossing = triangle(
+            vertex(-1.0, -1.0, -5.0),
+            vertex(1.0, -1.0, -5.0),
+            vertex(0.0, -1.0, 5.0),
+        );
+        let parts = camera.clip_triangle(&crossing);
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            for v in [&part.a, &part.b, &part.c] {
+                let depth = camera.view_depth(v.position);
+                assert!((1.0 - 1e-4..=100.0 + 1e-3).contains(&depth));
+
+                // Attributes are interpolated with the position
+                let uv = v.uv.unwrap();
+                assert!((uv.x - v.position.x).abs() < 1e-5);
+                assert!((uv.y - v.position.y).abs() < 1e-5);
+            }
+        }
+
+        // Clipping keeps the winding seen on screen, matching a triangle in the same plane which
+        // is entirely in front
+        let front = triangle(
+            vertex(-1.0, -1.0, -5.0),
+            vertex(1.0, -1.0, -5.0),
+            vertex(0.0, -1.0, -3.0),
+        );
+        let area = |t: &Triangle| {
+            let [a, b, c] = [&t.a, &t.b, &t.c].map(|v| camera.to_screen(v.position, 100, 100));
+            (b.xy() - a.xy()).perp(&(c.xy() - a.xy()))
+        };
+        let winding = area(&front).signum();
+        assert!(parts.iter().all(|part| area(part).signum() == winding));
+
+        // Triangles entirely in front or behind are kept whole or dropped
+        assert_eq!(camera.clip_triangle(&front).len(), 1);
+        let behind = triangle(
+            vertex(-1.0, -1.0, 5.0),
+            vertex(1.0, -1.0, 5.0),
+            vertex(0.0, 1.0, 5.0),
+        );
+        assert!(camera.clip_triangle(&behind).is_empty());
+    }
+}
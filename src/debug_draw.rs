@@ -0,0 +1,232 @@
+use image::Rgba;
+use nalgebra::{Point3, Vector3};
+
+use crate::camera::Camera;
+
+/// Number of straight segments in each circle
+const CIRCLE_SEGMENTS: u32 = 32;
+
+/// A world-space line segment with a colour
+pub struct DebugLine {
+    pub p0: Point3<f32>,
+    pub p1: Point3<f32>,
+    pub colour: Rgba<u8>,
+}
+
+/// A world-space position with a label, drawn as a marker
+pub struct Anchor {
+    pub position: Point3<f32>,
+    pub text: String,
+    pub colour: Rgba<u8>,
+}
+
+/// World-space annotations collected during a frame and drawn over it as lines when the frame is
+/// finished
+pub struct DebugDraw {
+    lines: Vec<DebugLine>,
+    anchors: Vec<Anchor>,
+    depth_test: bool,
+}
+
+impl DebugDraw {
+    /// Create an empty layer whose annotations are hidden by the geometry in front of them
+    pub fn default() -> DebugDraw {
+        DebugDraw {
+            lines: Vec::new(),
+            anchors: Vec::new(),
+            depth_test: true,
+        }
+    }
+
+    /// Get the lines collected since the last clear
+    #[inline]
+    pub fn get_lines(&self) -> &Vec<DebugLine> {
+        &self.lines
+    }
+
+    /// Get the anchors collected since the last clear
+    #[inline]
+    pub fn get_anchors(&self) -> &Vec<Anchor> {
+        &self.anchors
+    }
+
+    /// Get whether annotations are hidden by the geometry in front of them
+    #[inline]
+    pub fn get_depth_test(&self) -> bool {
+        self.depth_test
+    }
+
+    /// Set whether annotations are hidden by the geometry in front of them
+    pub fn set_depth_test(&mut self, depth_test: bool) {
+        self.depth_test = depth_test;
+    }
+
+    /// Remove and return the collected lines and anchors
+    pub fn take(&mut self) -> (Vec<DebugLine>, Vec<Anchor>) {
+        (
+            std::mem::take(&mut self.lines),
+            std::mem::take(&mut self.anchors),
+        )
+    }
+
+    /// Remove all collected lines and anchors
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.anchors.clear();
+    }
+
+    /// Add a line from `p0` to `p1`
+    pub fn line(&mut self, p0: Point3<f32>, p1: Point3<f32>, colour: Rgba<u8>) {
+        self.lines.push(DebugLine { p0, p1, colour });
+    }
+
+    /// Join each point to the next
+    pub fn polyline(&mut self, points: &[Point3<f32>], colour: Rgba<u8>) {
+        for pair in points.windows(2) {
+            self.line(pair[0], pair[1], colour);
+        }
+    }
+
+    /// Join each point to the next, and the last point back to the first
+    pub fn line_loop(&mut self, points: &[Point3<f32>], colour: Rgba<u8>) {
+        self.polyline(points, colour);
+        if let (Some(first), Some(last)) = (points.first(), points.last()) {
+            if points.len() > 2 {
+                self.line(*last, *first, colour);
+            }
+        }
+    }
+
+    /// Draw a line with a four-sided head at `to`
+    pub fn arrow(&mut self, from: Point3<f32>, to: Point3<f32>, colour: Rgba<u8>) {
+        self.line(from, to, colour);
+
+        let direction = to - from;
+        let length = direction.norm();
+        if length <= 0.0 {
+            return;
+        }
+
+        let (u, v) = perpendiculars(&(direction / length));
+        let base = to - direction * 0.2;
+        for side in [u, -u, v, -v] {
+            self.line(to, base + side * (length * 0.08), colour);
+        }
+    }
+
+    /// Draw the edges of an axis-aligned box
+    pub fn aabb(&mut self, min: Point3<f32>, max: Point3<f32>, colour: Rgba<u8>) {
+        let corner = |i: u32| {
+            Point3::<f32>::new(
+                if i & 1 == 0 { min.x } else { max.x },
+                if i & 2 == 0 { min.y } else { max.y },
+                if i & 4 == 0 { min.z } else { max.z },
+            )
+        };
+
+        // Join corners which differ along exactly one axis
+        for i in 0..8 {
+            for axis in [1, 2, 4] {
+                if i & axis == 0 {
+                    self.line(corner(i), corner(i | axis), colour);
+                }
+            }
+        }
+    }
+
+    /// Draw a circle in the plane with the given normal
+    pub fn circle(
+        &mut self,
+        centre: Point3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        colour: Rgba<u8>,
+    ) {
+        let (u, v) = perpendiculars(&normal.normalize());
+        let points: Vec<Point3<f32>> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                centre + (u * angle.cos() + v * angle.sin()) * radius
+            })
+            .collect();
+        self.line_loop(&points, colour);
+    }
+
+    /// Draw a sphere as a circle around each axis
+    pub fn sphere(&mut self, centre: Point3<f32>, radius: f32, colour: Rgba<u8>) {
+        for axis in [
+            Vector3::<f32>::x(),
+            Vector3::<f32>::y(),
+            Vector3::<f32>::z(),
+        ] {
+            self.circle(centre, axis, radius, colour);
+        }
+    }
+
+    /// Draw the X, Y and Z axes as red, green and blue arrows
+    pub fn axes(&mut self, origin: Point3<f32>, length: f32) {
+        self.arrow(
+            origin,
+            origin + Vector3::<f32>::x() * length,
+            Rgba([255, 0, 0, 255]),
+        );
+        self.arrow(
+            origin,
+            origin + Vector3::<f32>::y() * length,
+            Rgba([0, 255, 0, 255]),
+        );
+        self.arrow(
+            origin,
+            origin + Vector3::<f32>::z() * length,
+            Rgba([0, 0, 255, 255]),
+        );
+    }
+
+    /// Draw a square grid in the XY plane, with `divisions` cells along each side
+    pub fn grid(&mut self, centre: Point3<f32>, size: f32, divisions: u32, colour: Rgba<u8>) {
+        let half = size / 2.0;
+        for i in 0..=divisions {
+            let offset = -half + size * i as f32 / divisions.max(1) as f32;
+            self.line(
+                centre + Vector3::<f32>::new(offset, -half, 0.0),
+                centre + Vector3::<f32>::new(offset, half, 0.0),
+                colour,
+            );
+            self.line(
+                centre + Vector3::<f32>::new(-half, offset, 0.0),
+                centre + Vector3::<f32>::new(half, offset, 0.0),
+                colour,
+            );
+        }
+    }
+
+    /// Draw the edges of the volume a camera can see
+    pub fn frustum(&mut self, camera: &Camera, colour: Rgba<u8>) {
+        let corners = camera.get_frustum_corners();
+        self.line_loop(&corners[..4], colour);
+        self.line_loop(&corners[4..], colour);
+        for i in 0..4 {
+            self.line(corners[i], corners[i + 4], colour);
+        }
+    }
+
+    /// Mark a world-space position with a label
+    pub fn anchor(&mut self, position: Point3<f32>, text: &str, colour: Rgba<u8>) {
+        self.anchors.push(Anchor {
+            position,
+            text: text.to_string(),
+            colour,
+        });
+    }
+}
+
+/// Get two unit vectors perpendicular to a unit vector and to each other
+fn perpendiculars(direction: &Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let reference = if direction.x.abs() < 0.9 {
+        Vector3::<f32>::x()
+    } else {
+        Vector3::<f32>::y()
+    };
+    let u = direction.cross(&reference).normalize();
+    (u, direction.cross(&u))
+}
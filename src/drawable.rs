@@ -1,9 +1,12 @@
 use crate::renderer::Renderer;
 
 pub trait Drawable {
-    ///
+    /// Draw the object with its materials and the current config
     fn draw(&self, renderer: &mut Renderer);
 
-    ///
+    /// Draw only the edges of the object
     fn draw_wireframe(&self, renderer: &mut Renderer);
+
+    /// Draw the silhouettes, creases and boundaries selected by the renderer's config
+    fn draw_edges(&self, _renderer: &mut Renderer) {}
 }
@@ -0,0 +1,109 @@
+use nalgebra::Vector3;
+use std::f32::consts::PI;
+
+use crate::utilities;
+
+/// Resolution of the pre-convolved diffuse irradiance map
+const IRRADIANCE_WIDTH: u32 = 32;
+const IRRADIANCE_HEIGHT: u32 = 16;
+
+/// Equirectangular environment image used for image-based ambient lighting
+pub struct EnvironmentMap {
+    width: u32,
+    height: u32,
+    radiance: Vec<Vector3<f32>>,
+    irradiance: Vec<Vector3<f32>>,
+}
+
+impl EnvironmentMap {
+    /// Load an equirectangular environment from an sRGB image
+    pub fn load(filepath: &str) -> Result<EnvironmentMap, String> {
+        let image = image::open(filepath)
+            .map_err(|e| format!("Failed to load {}: {}", filepath, e))?
+            .to_rgb8();
+
+        let radiance = image
+            .pixels()
+            .map(|p| {
+                Vector3::<f32>::new(p[0] as f32, p[1] as f32, p[2] as f32)
+                    .map(|c| utilities::srgb_to_linear(c / 255.0))
+            })
+            .collect();
+
+        let mut environment_map = EnvironmentMap {
+            width: image.width(),
+            height: image.height(),
+            radiance,
+            irradiance: Vec::new(),
+        };
+        environment_map.irradiance = environment_map.convolve_irradiance();
+        Ok(environment_map)
+    }
+
+    /// Sample the environment in a world-space direction
+    pub fn sample(&self, direction: &Vector3<f32>) -> Vector3<f32> {
+        let (x, y) = EnvironmentMap::to_texel(direction, self.width, self.height);
+        self.radiance[(y * self.width + x) as usize]
+    }
+
+    /// Sample the cosine-weighted irradiance arriving at a surface with the given normal
+    pub fn sample_irradiance(&self, normal: &Vector3<f32>) -> Vector3<f32> {
+        let (x, y) = EnvironmentMap::to_texel(normal, IRRADIANCE_WIDTH, IRRADIANCE_HEIGHT);
+        self.irradiance[(y * IRRADIANCE_WIDTH + x) as usize]
+    }
+
+    /// Map a direction onto equirectangular texel coordinates, with Z as the up axis
+    fn to_texel(direction: &Vector3<f32>, width: u32, height: u32) -> (u32, u32) {
+        let d = direction.normalize();
+        let u = d.y.atan2(d.x) / (2.0 * PI) + 0.5;
+        let v = d.z.clamp(-1.0, 1.0).acos() / PI;
+        (
+            ((u * width as f32) as u32).min(width - 1),
+            ((v * height as f32) as u32).min(height - 1),
+        )
+    }
+
+    /// Map equirectangular texel coordinates onto a direction
+    fn to_direction(x: u32, y: u32, width: u32, height: u32) -> Vector3<f32> {
+        let phi = ((x as f32 + 0.5) / width as f32 - 0.5) * 2.0 * PI;
+        let theta = ((y as f32 + 0.5) / height as f32) * PI;
+        Vector3::<f32>::new(
+            theta.sin() * phi.cos(),
+            theta.sin() * phi.sin(),
+            theta.cos(),
+        )
+    }
+
+    /// Pre-convolve a low resolution copy of the environment with a cosine lobe
+    fn convolve_irradiance(&self) -> Vec<Vector3<f32>> {
+        // Downsample the radiance, weighting each texel by the solid angle it covers
+        let mut samples = Vec::<(Vector3<f32>, Vector3<f32>)>::new();
+        for y in 0..IRRADIANCE_HEIGHT {
+            for x in 0..IRRADIANCE_WIDTH {
+                let direction =
+                    EnvironmentMap::to_direction(x, y, IRRADIANCE_WIDTH, IRRADIANCE_HEIGHT);
+                let theta = ((y as f32 + 0.5) / IRRADIANCE_HEIGHT as f32) * PI;
+                let solid_angle = theta.sin()
+                    * (2.0 * PI / IRRADIANCE_WIDTH as f32)
+                    * (PI / IRRADIANCE_HEIGHT as f32);
+                samples.push((direction, self.sample(&direction) * solid_angle));
+            }
+        }
+
+        let mut irradiance = Vec::with_capacity(samples.len());
+        for y in 0..IRRADIANCE_HEIGHT {
+            for x in 0..IRRADIANCE_WIDTH {
+                let normal =
+                    EnvironmentMap::to_direction(x, y, IRRADIANCE_WIDTH, IRRADIANCE_HEIGHT);
+                let sum = samples
+                    .iter()
+                    .fold(Vector3::<f32>::zeros(), |sum, (direction, radiance)| {
+                        sum + radiance * normal.dot(direction).max(0.0)
+                    });
+                irradiance.push(sum / PI);
+            }
+        }
+
+        irradiance
+    }
+}
@@ -0,0 +1,64 @@
+use nalgebra::{Point3, Vector2, Vector3};
+
+/// Identifier of a sample which no triangle has covered
+pub const NO_ID: u32 = u32::MAX;
+
+/// Identifiers of the drawable, triangle and material which produced a sample. Objects are
+/// numbered in the order they are drawn each frame, triangles in the order each object submits
+/// them, and materials in the order they are first used each frame
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PrimitiveIds {
+    pub object: u32,
+    pub triangle: u32,
+    pub material: u32,
+}
+
+impl PrimitiveIds {
+    /// Get the IDs of a sample which no primitive has covered
+    pub fn none() -> PrimitiveIds {
+        PrimitiveIds {
+            object: NO_ID,
+            triangle: NO_ID,
+            material: NO_ID,
+        }
+    }
+}
+
+/// The triangle covering a sample, with the perspective-correct barycentric coordinates and
+/// world-space position of the sample on it
+#[derive(Clone, Copy, Debug)]
+pub struct Hit {
+    pub ids: PrimitiveIds,
+    pub barycentrics: Vector3<f32>,
+    pub position: Point3<f32>,
+}
+
+/// Surface attributes of the nearest opaque triangle at a sample, with world-space position and
+/// normal. The normal includes any normal map
+#[derive(Clone, Copy)]
+pub struct GBufferSample {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub albedo: Vector3<f32>,
+    pub uv: Vector2<f32>,
+    pub ids: PrimitiveIds,
+}
+
+impl GBufferSample {
+    /// Get a sample for the background, where nothing has been drawn
+    pub fn empty() -> GBufferSample {
+        GBufferSample {
+            position: Point3::<f32>::origin(),
+            normal: Vector3::<f32>::zeros(),
+            albedo: Vector3::<f32>::zeros(),
+            uv: Vector2::<f32>::zeros(),
+            ids: PrimitiveIds::none(),
+        }
+    }
+
+    /// Check whether no primitive has covered the sample
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.ids.material == NO_ID
+    }
+}
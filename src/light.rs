@@ -0,0 +1,130 @@
+use nalgebra::{Point3, Vector3};
+
+use crate::environment_map::EnvironmentMap;
+
+pub enum Light {
+    Directional {
+        direction: Vector3<f32>,
+        colour: Vector3<f32>,
+        intensity: f32,
+    },
+    Point {
+        position: Point3<f32>,
+        colour: Vector3<f32>,
+        intensity: f32,
+    },
+    Spot {
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+        colour: Vector3<f32>,
+        intensity: f32,
+    },
+}
+
+impl Light {
+    /// Create a light shining in `direction` from infinitely far away
+    pub fn directional(direction: Vector3<f32>, colour: Vector3<f32>, intensity: f32) -> Light {
+        Light::Directional {
+            direction: direction.normalize(),
+            colour,
+            intensity,
+        }
+    }
+
+    /// Create a light shining in every direction from `position`
+    pub fn point(position: Point3<f32>, colour: Vector3<f32>, intensity: f32) -> Light {
+        Light::Point {
+            position,
+            colour,
+            intensity,
+        }
+    }
+
+    /// Create a cone of light, fading out between the inner and outer angles in radians
+    pub fn spot(
+        position: Point3<f32>,
+        direction: Vector3<f32>,
+        inner_angle: f32,
+        outer_angle: f32,
+        colour: Vector3<f32>,
+        intensity: f32,
+    ) -> Light {
+        Light::Spot {
+            position,
+            direction: direction.normalize(),
+            inner_angle,
+            outer_angle,
+            colour,
+            intensity,
+        }
+    }
+
+    /// Get the direction towards the light and the radiance arriving at a world-space position
+    pub fn illuminate(&self, position: &Point3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+        match self {
+            Light::Directional {
+                direction,
+                colour,
+                intensity,
+            } => (-direction, colour * *intensity),
+            Light::Point {
+                position: light_position,
+                colour,
+                intensity,
+            } => {
+                let offset = light_position - position;
+                let distance_squared = offset.norm_squared().max(f32::EPSILON);
+                (offset.normalize(), colour * (*intensity / distance_squared))
+            }
+            Light::Spot {
+                position: light_position,
+                direction,
+                inner_angle,
+                outer_angle,
+                colour,
+                intensity,
+            } => {
+                let offset = light_position - position;
+                let distance_squared = offset.norm_squared().max(f32::EPSILON);
+                let to_light = offset.normalize();
+
+                // Fade out between the inner and outer cone angles
+                let cos_angle = (-to_light).dot(direction);
+                let cos_inner = inner_angle.cos();
+                let cos_outer = outer_angle.cos();
+                let cone = ((cos_angle - cos_outer) / (cos_inner - cos_outer).max(f32::EPSILON))
+                    .clamp(0.0, 1.0);
+
+                (to_light, colour * (*intensity * cone / distance_squared))
+            }
+        }
+    }
+}
+
+/// Light arriving from the surroundings rather than from a specific light source
+pub enum AmbientLight {
+    Constant(Vector3<f32>),
+    Image(EnvironmentMap),
+}
+
+impl AmbientLight {
+    /// Get the diffuse irradiance arriving at a surface with the given normal
+    pub fn irradiance(&self, normal: &Vector3<f32>) -> Vector3<f32> {
+        match self {
+            AmbientLight::Constant(colour) => *colour,
+            AmbientLight::Image(environment_map) => environment_map.sample_irradiance(normal),
+        }
+    }
+
+    /// Get the radiance reflected towards a direction by a surface with the given roughness
+    pub fn reflection(&self, direction: &Vector3<f32>, roughness: f32) -> Vector3<f32> {
+        match self {
+            AmbientLight::Constant(colour) => *colour,
+            AmbientLight::Image(environment_map) => environment_map
+                .sample(direction)
+                .lerp(&environment_map.sample_irradiance(direction), roughness),
+        }
+    }
+}
@@ -1,15 +1,30 @@
 #![allow(dead_code)]
 
+use image::Rgba;
 use minifb::{Key, Window, WindowOptions};
 use nalgebra::Point3;
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefMutIterator, ParallelIterator};
 use std::time::Instant;
 
 mod camera;
+mod debug_draw;
 mod drawable;
+mod environment_map;
+mod g_buffer;
+mod light;
+mod material;
 mod mesh;
+mod point_cloud;
+mod post_process;
+mod rasterizer;
 mod renderer;
 mod renderer_config;
+mod screen_space;
+mod shading;
+mod shadow_map;
+mod text;
+mod texture;
+mod tile;
 mod transformable;
 mod utilities;
 
@@ -22,9 +37,14 @@ const WIDTH: u32 = 1280;
 const HEIGHT: u32 = 720;
 
 fn main() {
+    if std::env::args().any(|argument| argument == "--benchmark") {
+        benchmark();
+        return;
+    }
+
     // Init window
     let mut window = Window::new(
-        "Loading...",
+        "Minidraw",
         WIDTH as usize,
         HEIGHT as usize,
         WindowOptions::default(),
@@ -32,15 +52,11 @@ fn main() {
     .expect("Failed to create window");
 
     // Init renderer
-    let config = RendererConfig::default();
-    let mut renderer = Renderer::new(WIDTH, HEIGHT, config);
-    renderer
-        .get_camera_mut()
-        .set_position(&Point3::<f32>::new(25.0, 25.0, 25.0));
-    renderer.get_camera_mut().look_at(&Point3::<f32>::origin());
+    let mut renderer = create_renderer();
 
     // Load mesh
     let mut mesh = Mesh::load_obj("models/teapot.obj").expect("Failed to load model");
+    let ground = Mesh::plane(20.0);
 
     let mut buffer: Vec<u32> = vec![0; (WIDTH * HEIGHT) as usize];
     let mut frame_timer = Instant::now();
@@ -50,11 +66,15 @@ fn main() {
         let frame_duration = frame_timer.elapsed().as_secs_f32();
         let frame_rate = 1.0 / frame_duration;
         frame_timer = Instant::now();
-        window.set_title(&format!("Minidraw ({:.2}fps)", frame_rate));
 
         // Draw
-        renderer.clear();
-        renderer.draw(&mesh);
+        render_frame(&mut renderer, &mesh, &ground);
+        renderer.text(
+            &format!("{:.2}fps", frame_rate),
+            8.0,
+            8.0,
+            Rgba([255, 255, 255, 255]),
+        );
 
         // Adapt to frame buffer
         frame_copy(renderer.get_colour_buffer_raw(), &mut buffer);
@@ -79,8 +99,74 @@ fn main() {
     }
 }
 
-///
-fn frame_copy(src: &Vec<u8>, dst: &mut Vec<u32>) {
+/// Create a renderer for the window, with the camera looking at the origin
+fn create_renderer() -> Renderer {
+    let config = RendererConfig::default();
+    let mut renderer = Renderer::new(WIDTH, HEIGHT, config);
+    renderer
+        .get_camera_mut()
+        .set_position(&Point3::<f32>::new(25.0, 25.0, 25.0));
+    renderer.get_camera_mut().look_at(&Point3::<f32>::origin());
+    renderer
+}
+
+/// Draw the mesh on the ground, shadowed by the mesh
+fn render_frame(renderer: &mut Renderer, mesh: &Mesh, ground: &Mesh) {
+    renderer.clear();
+    renderer.cast_shadows(mesh);
+    renderer.draw(ground);
+    renderer.draw(mesh);
+    renderer.finish();
+}
+
+/// Render the scene without a window using an increasing number of threads, reporting the frame
+/// time of each
+fn benchmark() {
+    const FRAMES: u32 = 50;
+
+    let mesh = Mesh::load_obj("models/teapot.obj").expect("Failed to load model");
+    let ground = Mesh::plane(20.0);
+
+    // Double the number of threads up to the number available
+    let max_threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let mut thread_counts: Vec<usize> = std::iter::successors(Some(1), |n| Some(n * 2))
+        .take_while(|n| *n < max_threads)
+        .collect();
+    thread_counts.push(max_threads);
+
+    let mut baseline = None;
+
+    for threads in thread_counts {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("Failed to create thread pool");
+
+        let frame_time = pool.install(|| {
+            let mut renderer = create_renderer();
+
+            // Warm up before timing
+            render_frame(&mut renderer, &mesh, &ground);
+
+            let start = Instant::now();
+            for _ in 0..FRAMES {
+                render_frame(&mut renderer, &mesh, &ground);
+            }
+            start.elapsed().as_secs_f32() / FRAMES as f32
+        });
+
+        let baseline = *baseline.get_or_insert(frame_time);
+        println!(
+            "{:>3} threads: {:>8.2}ms per frame ({:.2}x)",
+            threads,
+            frame_time * 1000.0,
+            baseline / frame_time
+        );
+    }
+}
+
+/// Copy an RGBA colour buffer into the 0RGB pixels of the window
+fn frame_copy(src: &[u8], dst: &mut [u32]) {
     dst.par_iter_mut()
         .enumerate()
         .for_each(|(offset_dst, pixel)| {
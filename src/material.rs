@@ -0,0 +1,188 @@
+use nalgebra::Vector3;
+use std::fs;
+use std::path::Path;
+
+use crate::texture::{ColourSpace, Texture, WrapMode};
+use crate::utilities;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    Additive,
+    Multiply,
+}
+
+impl BlendMode {
+    /// Combine a source colour with the colour already in the colour buffer
+    pub fn blend(
+        &self,
+        source: &Vector3<f32>,
+        alpha: f32,
+        destination: &Vector3<f32>,
+    ) -> Vector3<f32> {
+        match self {
+            BlendMode::Opaque => *source,
+            BlendMode::Alpha => destination.lerp(source, alpha),
+            BlendMode::Additive => destination + source * alpha,
+            BlendMode::Multiply => {
+                destination.component_mul(&Vector3::<f32>::repeat(1.0).lerp(source, alpha))
+            }
+        }
+    }
+}
+
+pub struct Material {
+    pub name: String,
+    pub ambient: Vector3<f32>,
+    pub diffuse: Vector3<f32>,
+    pub specular: Vector3<f32>,
+    pub emissive: Vector3<f32>,
+    pub shininess: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub occlusion: f32,
+    pub dissolve: f32,
+    pub blend_mode: BlendMode,
+    pub diffuse_texture: Option<Texture>,
+    pub normal_texture: Option<Texture>,
+}
+
+impl Material {
+    /// Create a matte white material
+    pub fn default() -> Material {
+        Material {
+            name: String::from("default"),
+            ambient: Vector3::<f32>::new(1.0, 1.0, 1.0),
+            diffuse: Vector3::<f32>::new(0.8, 0.8, 0.8),
+            specular: Vector3::<f32>::new(0.0, 0.0, 0.0),
+            emissive: Vector3::<f32>::new(0.0, 0.0, 0.0),
+            shininess: 1.0,
+            metallic: 0.0,
+            roughness: 0.5,
+            occlusion: 1.0,
+            dissolve: 1.0,
+            blend_mode: BlendMode::Opaque,
+            diffuse_texture: None,
+            normal_texture: None,
+        }
+    }
+
+    /// Load all materials defined in an MTL file
+    pub fn load_mtl<P: AsRef<Path>>(filepath: P) -> Result<Vec<Material>, String> {
+        let filepath = filepath.as_ref();
+        let source = fs::read_to_string(filepath)
+            .map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+
+        let mut materials = Vec::<Material>::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let mut tokens = line.split_whitespace();
+            let keyword = match tokens.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+            let arguments: Vec<&str> = tokens.collect();
+
+            if keyword == "newmtl" {
+                let mut material = Material::default();
+                material.name = arguments.join(" ");
+                materials.push(material);
+                continue;
+            }
+
+            let material = match materials.last_mut() {
+                Some(material) => material,
+                None => continue,
+            };
+
+            let error = || {
+                format!(
+                    "{}:{}: Invalid '{}'",
+                    filepath.display(),
+                    line_number + 1,
+                    keyword
+                )
+            };
+
+            match keyword {
+                "Ka" => material.ambient = parse_colour(&arguments).ok_or_else(error)?,
+                "Kd" => material.diffuse = parse_colour(&arguments).ok_or_else(error)?,
+                "Ks" => material.specular = parse_colour(&arguments).ok_or_else(error)?,
+                "Ke" => material.emissive = parse_colour(&arguments).ok_or_else(error)?,
+                "Ns" => {
+                    material.shininess = parse_scalar(&arguments).ok_or_else(error)?;
+                    // Approximate roughness from the Phong exponent, overridden by "Pr"
+                    material.roughness = (2.0 / (material.shininess + 2.0)).sqrt();
+                }
+                "Pm" => material.metallic = parse_scalar(&arguments).ok_or_else(error)?,
+                "Pr" => material.roughness = parse_scalar(&arguments).ok_or_else(error)?,
+                "d" => material.dissolve = parse_scalar(&arguments).ok_or_else(error)?,
+                "Tr" => material.dissolve = 1.0 - parse_scalar(&arguments).ok_or_else(error)?,
+                "map_Kd" => {
+                    material.diffuse_texture = load_texture(filepath, &arguments, ColourSpace::Srgb)
+                }
+                "map_Bump" | "map_bump" | "bump" | "norm" => {
+                    material.normal_texture =
+                        load_texture(filepath, &arguments, ColourSpace::Linear)
+                }
+                // Unsupported statements are ignored
+                _ => {}
+            }
+        }
+
+        // Partially dissolved materials are alpha blended
+        for material in &mut materials {
+            if material.dissolve < 1.0 {
+                material.blend_mode = BlendMode::Alpha;
+            }
+        }
+
+        Ok(materials)
+    }
+}
+
+/// Parse an MTL colour statement, where a single value is used for all channels. Colours are
+/// written in sRGB, and decoded to linear
+fn parse_colour(arguments: &[&str]) -> Option<Vector3<f32>> {
+    let values: Vec<f32> = arguments
+        .iter()
+        .map(|a| a.parse::<f32>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+
+    let colour = match values.len() {
+        1 => Vector3::<f32>::new(values[0], values[0], values[0]),
+        3 => Vector3::<f32>::new(values[0], values[1], values[2]),
+        _ => return None,
+    };
+    Some(colour.map(utilities::srgb_to_linear))
+}
+
+/// Load the texture referenced by an MTL texture map statement, relative to the MTL file
+fn load_texture(filepath: &Path, arguments: &[&str], colour_space: ColourSpace) -> Option<Texture> {
+    // Options precede the filename, which is always the final argument
+    let (filename, options) = arguments.split_last()?;
+    let texture_path = filepath.parent()?.join(filename);
+
+    let mut texture = match Texture::load(&texture_path.to_string_lossy(), colour_space) {
+        Ok(texture) => texture,
+        Err(e) => {
+            eprintln!("{}", e);
+            return None;
+        }
+    };
+
+    if let Some(index) = options.iter().position(|o| *o == "-clamp") {
+        if options.get(index + 1) == Some(&"on") {
+            texture.wrap = WrapMode::Clamp;
+        }
+    }
+
+    Some(texture)
+}
+
+/// Parse the first argument of an MTL statement as a number
+fn parse_scalar(arguments: &[&str]) -> Option<f32> {
+    arguments.first()?.parse::<f32>().ok()
+}
@@ -0,0 +1,57 @@
+use nalgebra::Point3;
+use std::collections::HashMap;
+
+use super::triangle::Triangle;
+
+/// An edge between triangles of a mesh, with the indices of every triangle which shares it
+pub struct Edge {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub faces: Vec<usize>,
+}
+
+impl Edge {
+    /// Find the edges of a set of triangles, joining triangles whose vertices share positions
+    pub fn find_all(triangles: &[Triangle]) -> Vec<Edge> {
+        let mut edges = Vec::<Edge>::new();
+        let mut indices = HashMap::<([u32; 3], [u32; 3]), usize>::new();
+        let key = |p: &Point3<f32>| [p.x, p.y, p.z].map(f32::to_bits);
+
+        for (face, triangle) in triangles.iter().enumerate() {
+            let positions = [
+                triangle.a.position,
+                triangle.b.position,
+                triangle.c.position,
+            ];
+
+            for i in 0..3 {
+                let (a, b) = (positions[i], positions[(i + 1) % 3]);
+                let (key_a, key_b) = (key(&a), key(&b));
+                if key_a == key_b {
+                    continue;
+                }
+
+                // Neighbouring triangles run along a shared edge in opposite directions
+                let ordered = if key_a < key_b {
+                    (key_a, key_b)
+                } else {
+                    (key_b, key_a)
+                };
+
+                match indices.get(&ordered) {
+                    Some(index) => edges[*index].faces.push(face),
+                    None => {
+                        indices.insert(ordered, edges.len());
+                        edges.push(Edge {
+                            a,
+                            b,
+                            faces: vec![face],
+                        });
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+}
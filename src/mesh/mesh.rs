@@ -1,8 +1,13 @@
 use image::Rgba;
-use nalgebra::{Matrix4, Point3, Vector3};
-use obj::Obj;
+use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use obj::{IndexTuple, Obj, ObjData, ObjMaterial};
+use std::collections::HashMap;
+use std::sync::Arc;
 
+use crate::debug_draw::DebugDraw;
 use crate::drawable::Drawable;
+use crate::material::Material;
+use crate::mesh::edge::Edge;
 use crate::mesh::triangle::Triangle;
 use crate::mesh::vertex::Vertex;
 use crate::renderer::Renderer;
@@ -10,105 +15,323 @@ use crate::transformable::Transformable;
 
 pub struct Mesh {
     geometry: Vec<Triangle>,
+    edges: Vec<Edge>,
+    materials: Vec<Arc<Material>>,
     transform: Matrix4<f32>,
 }
 
 impl Mesh {
     pub fn load_obj(filename: &str) -> Result<Mesh, String> {
-        let data = Obj::load(filename).unwrap().data;
+        let obj = Obj::load(filename).map_err(|e| format!("Failed to load {}: {}", filename, e))?;
+        let data = &obj.data;
+
+        // Load the referenced material libraries, falling back to a default material
+        let mut materials = vec![Material::default()];
+        for library in &data.material_libs {
+            match Material::load_mtl(obj.path.join(&library.filename)) {
+                Ok(library_materials) => materials.extend(library_materials),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
         let mut triangles = Vec::<Triangle>::new();
 
         for object in &data.objects {
             for group in &object.groups {
+                // Find the material used by this group
+                let material = match &group.material {
+                    Some(ObjMaterial::Ref(name)) => {
+                        materials.iter().position(|m| &m.name == name).unwrap_or(0)
+                    }
+                    Some(ObjMaterial::Mtl(mtl)) => materials
+                        .iter()
+                        .position(|m| m.name == mtl.name)
+                        .unwrap_or(0),
+                    None => 0,
+                };
+
                 for polygon in &group.polys {
                     // Ignore polygons that are not triangles
                     if polygon.0.len() != 3 {
                         continue;
                     }
 
-                    // Get the indicies of the points of the triangle
-                    let idx0 = polygon.0[0].0;
-                    let idx1 = polygon.0[1].0;
-                    let idx2 = polygon.0[2].0;
-
-                    let indices = vec![idx0, idx1, idx2];
-                    let positions: Vec<_> = indices
-                        .iter()
-                        .map(|i| data.position[*i])
-                        .map(|p| Point3::<f32>::new(p[0], p[1], p[2]))
-                        .collect();
-
                     triangles.push(Triangle {
-                        a: Vertex {
-                            position: positions[0],
-                            normal: None,
-                            uv: None,
-                            colour: None,
-                        },
-                        b: Vertex {
-                            position: positions[1],
-                            normal: None,
-                            uv: None,
-                            colour: None,
-                        },
-                        c: Vertex {
-                            position: positions[2],
-                            normal: None,
-                            uv: None,
-                            colour: None,
-                        },
+                        a: Mesh::vertex(data, &polygon.0[0]),
+                        b: Mesh::vertex(data, &polygon.0[1]),
+                        c: Mesh::vertex(data, &polygon.0[2]),
                         colour: Rgba([255, 255, 255, 255]),
+                        material,
                     });
                 }
             }
         }
 
-        return Ok(Mesh {
+        let mut mesh = Mesh {
+            edges: Edge::find_all(&triangles),
             geometry: triangles,
+            materials: materials.into_iter().map(Arc::new).collect(),
             transform: Matrix4::<f32>::identity(),
-        });
+        };
+        mesh.generate_tangents();
+        Ok(mesh)
+    }
+
+    /// Create a square in the XY plane, centred on the origin and facing +Z
+    pub fn plane(size: f32) -> Mesh {
+        let half = size / 2.0;
+        let corners = [(-half, -half), (half, -half), (half, half), (-half, half)];
+        let vertex = |(x, y): (f32, f32)| Vertex {
+            position: Point3::<f32>::new(x, y, 0.0),
+            normal: Some(Vector3::<f32>::z()),
+            tangent: None,
+            uv: Some(Vector2::<f32>::new(x / size + 0.5, y / size + 0.5)),
+            colour: None,
+        };
+
+        let triangles: Vec<Triangle> = [[0, 1, 2], [0, 2, 3]]
+            .iter()
+            .map(|[a, b, c]| Triangle {
+                a: vertex(corners[*a]),
+                b: vertex(corners[*b]),
+                c: vertex(corners[*c]),
+                colour: Rgba([255, 255, 255, 255]),
+                material: 0,
+            })
+            .collect();
+
+        let mut mesh = Mesh {
+            edges: Edge::find_all(&triangles),
+            geometry: triangles,
+            materials: vec![Arc::new(Material::default())],
+            transform: Matrix4::<f32>::identity(),
+        };
+        mesh.generate_tangents();
+        mesh
+    }
+
+    /// Get the triangles of the mesh before its transform, in the order they are drawn, so
+    /// picked triangle IDs index into them
+    #[inline]
+    pub fn get_geometry(&self) -> &Vec<Triangle> {
+        &self.geometry
+    }
+
+    /// Add a line along the normal of each vertex to a debug-draw layer
+    pub fn debug_normals(&self, debug_draw: &mut DebugDraw, length: f32, colour: Rgba<u8>) {
+        for triangle in &self.geometry {
+            let transformed_triangle = triangle.transform(&self.transform);
+            for vertex in [
+                &transformed_triangle.a,
+                &transformed_triangle.b,
+                &transformed_triangle.c,
+            ] {
+                if let Some(normal) = vertex.normal {
+                    debug_draw.line(
+                        vertex.position,
+                        vertex.position + normal.normalize() * length,
+                        colour,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Generate per-vertex tangents following MikkTSpace, from positions, normals and UVs
+    pub fn generate_tangents(&mut self) {
+        // Corners are grouped by vertex and by the handedness of their UV mapping, so vertices
+        // on a mirrored seam are split rather than averaged
+        let mut accumulated = HashMap::<(VertexKey, bool), Vector3<f32>>::new();
+        let mut handedness = Vec::<Option<bool>>::with_capacity(self.geometry.len());
+
+        for triangle in &self.geometry {
+            let vertices = [&triangle.a, &triangle.b, &triangle.c];
+            let (tangent, orientation_preserving) = match Mesh::face_tangent(vertices) {
+                Some(face) => face,
+                None => {
+                    handedness.push(None);
+                    continue;
+                }
+            };
+            handedness.push(Some(orientation_preserving));
+
+            for i in 0..3 {
+                let normal = match vertices[i].normal {
+                    Some(normal) => normal.normalize(),
+                    None => continue,
+                };
+                let project =
+                    |v: Vector3<f32>| (v - normal * normal.dot(&v)).try_normalize(f32::EPSILON);
+
+                // Weight the face tangent, projected into the plane of the corner's normal, by
+                // the angle of the corner in that plane
+                let edge0 = project(vertices[(i + 1) % 3].position - vertices[i].position);
+                let edge1 = project(vertices[(i + 2) % 3].position - vertices[i].position);
+                let (tangent, edge0, edge1) = match (project(tangent), edge0, edge1) {
+                    (Some(tangent), Some(edge0), Some(edge1)) => (tangent, edge0, edge1),
+                    _ => continue,
+                };
+                let angle = edge0.dot(&edge1).clamp(-1.0, 1.0).acos();
+
+                *accumulated
+                    .entry((VertexKey::new(vertices[i]), orientation_preserving))
+                    .or_insert_with(Vector3::<f32>::zeros) += tangent * angle;
+            }
+        }
+
+        for (triangle, orientation_preserving) in self.geometry.iter_mut().zip(handedness) {
+            let orientation_preserving = match orientation_preserving {
+                Some(orientation_preserving) => orientation_preserving,
+                None => continue,
+            };
+            for vertex in [&mut triangle.a, &mut triangle.b, &mut triangle.c] {
+                let key = (VertexKey::new(vertex), orientation_preserving);
+                let tangent = match accumulated.get(&key) {
+                    Some(sum) => sum.try_normalize(f32::EPSILON),
+                    None => None,
+                };
+                let sign = if orientation_preserving { 1.0 } else { -1.0 };
+                if let Some(tangent) = tangent {
+                    vertex.tangent = Some(tangent.push(sign));
+                }
+            }
+        }
+    }
+
+    /// Get the unit tangent of a face along increasing U, and whether its UV mapping preserves
+    /// orientation, or `None` if its texture coordinates are missing or degenerate
+    fn face_tangent(vertices: [&Vertex; 3]) -> Option<(Vector3<f32>, bool)> {
+        let uvs = [vertices[0].uv?, vertices[1].uv?, vertices[2].uv?];
+        let e1 = vertices[1].position - vertices[0].position;
+        let e2 = vertices[2].position - vertices[0].position;
+        let duv1 = uvs[1] - uvs[0];
+        let duv2 = uvs[2] - uvs[0];
+
+        let signed_area = duv1.perp(&duv2);
+        if signed_area.abs() < f32::EPSILON {
+            return None;
+        }
+
+        let tangent = (e1 * duv2.y - e2 * duv1.y) * signed_area.signum();
+        Some((tangent.try_normalize(f32::EPSILON)?, signed_area > 0.0))
+    }
+
+    /// Build a vertex from the attributes referenced by an OBJ face index
+    fn vertex(data: &ObjData, index: &IndexTuple) -> Vertex {
+        let p = data.position[index.0];
+        Vertex {
+            position: Point3::<f32>::new(p[0], p[1], p[2]),
+            normal: index
+                .2
+                .map(|i| data.normal[i])
+                .map(|n| Vector3::<f32>::new(n[0], n[1], n[2])),
+            tangent: None,
+            uv: index
+                .1
+                .map(|i| data.texture[i])
+                .map(|t| Vector2::<f32>::new(t[0], t[1])),
+            colour: None,
+        }
+    }
+}
+
+/// Identifies vertices which share a position, normal and texture coordinate
+#[derive(PartialEq, Eq, Hash)]
+struct VertexKey([u32; 8]);
+
+impl VertexKey {
+    /// Quantize the attributes of a vertex so that equal vertices hash equally
+    fn new(vertex: &Vertex) -> VertexKey {
+        let normal = vertex.normal.unwrap_or_else(Vector3::<f32>::zeros);
+        let uv = vertex.uv.unwrap_or_else(Vector2::<f32>::zeros);
+        VertexKey(
+            [
+                vertex.position.x,
+                vertex.position.y,
+                vertex.position.z,
+                normal.x,
+                normal.y,
+                normal.z,
+                uv.x,
+                uv.y,
+            ]
+            .map(f32::to_bits),
+        )
     }
 }
 
 impl Drawable for Mesh {
-    ///
     fn draw(&self, renderer: &mut Renderer) {
         for triangle in &self.geometry {
             let transformed_triangle = triangle.transform(&self.transform);
-            renderer.triangle(&transformed_triangle);
+            renderer.triangle(&transformed_triangle, &self.materials[triangle.material]);
         }
     }
 
-    ///
     fn draw_wireframe(&self, renderer: &mut Renderer) {
         for triangle in &self.geometry {
             let transformed_triangle = triangle.transform(&self.transform);
-            renderer.line(
+            let colour = renderer
+                .get_config()
+                .wireframe_colour
+                .unwrap_or(transformed_triangle.colour);
+            let (a, b, c) = (
                 transformed_triangle.a.position,
-                transformed_triangle.b.position,
-                transformed_triangle.colour,
-            );
-            renderer.line(
                 transformed_triangle.b.position,
                 transformed_triangle.c.position,
-                transformed_triangle.colour,
-            );
-            renderer.line(
-                transformed_triangle.c.position,
-                transformed_triangle.a.position,
-                transformed_triangle.colour,
             );
+
+            renderer.line(a, b, colour);
+            renderer.line(b, c, colour);
+            renderer.line(c, a, colour);
+        }
+    }
+
+    fn draw_edges(&self, renderer: &mut Renderer) {
+        let feature_edges = renderer.get_config().feature_edges;
+        let camera = renderer.get_camera();
+
+        // World-space normal of each face, and whether its front faces the camera
+        let faces: Vec<(Vector3<f32>, bool)> = self
+            .geometry
+            .iter()
+            .map(|triangle| {
+                let triangle = triangle.transform(&self.transform);
+                let normal = (triangle.b.position - triangle.a.position)
+                    .cross(&(triangle.c.position - triangle.a.position))
+                    .normalize();
+                (normal, camera.is_facing(&triangle.a.position, &normal))
+            })
+            .collect();
+        let crease_cos = feature_edges.crease_angle.cos();
+
+        for edge in &self.edges {
+            // Edges shared by more than two faces are drawn like boundaries
+            let drawn = match edge.faces[..] {
+                [first, second] => {
+                    let (first, second) = (faces[first], faces[second]);
+                    (feature_edges.silhouettes && first.1 != second.1)
+                        || (feature_edges.creases && first.0.dot(&second.0) < crease_cos)
+                }
+                _ => feature_edges.boundaries,
+            };
+
+            if drawn {
+                renderer.line(
+                    self.transform.transform_point(&edge.a),
+                    self.transform.transform_point(&edge.b),
+                    feature_edges.colour,
+                );
+            }
         }
     }
 }
 
 impl Transformable for Mesh {
-    ///
     fn translate(&mut self, delta: Vector3<f32>) {
         self.transform *= Matrix4::<f32>::new_translation(&delta);
     }
 
-    ///
     fn rotate(&mut self, x: f32, y: f32, z: f32) {
         let rot_x = Vector3::x() * x;
         let rot_y = Vector3::y() * y;
@@ -116,8 +339,62 @@ impl Transformable for Mesh {
         self.transform *= Matrix4::<f32>::new_rotation(rot_x + rot_y + rot_z);
     }
 
-    ///
     fn scale(&mut self, factor: f32) {
         self.transform *= Matrix4::<f32>::new_scaling(factor);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create an untransformed mesh from triangles with one default material
+    fn mesh(geometry: Vec<Triangle>) -> Mesh {
+        Mesh {
+            edges: Edge::find_all(&geometry),
+            geometry,
+            materials: vec![Arc::new(Material::default())],
+            transform: Matrix4::<f32>::identity(),
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_split_tangents_by_handedness() {
+        // Two quads facing +Z whose U coordinate mirrors at x = 0, as on a symmetric model
+        let vertex = |x: f32, y: f32| Vertex {
+            position: Point3::<f32>::new(x, y, 0.0),
+            normal: Some(Vector3::<f32>::z()),
+            tangent: None,
+            uv: Some(Vector2::<f32>::new(x.abs(), y)),
+            colour: None,
+        };
+        let triangle = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| Triangle {
+            a: vertex(a.0, a.1),
+            b: vertex(b.0, b.1),
+            c: vertex(c.0, c.1),
+            colour: Rgba([255, 255, 255, 255]),
+            material: 0,
+        };
+        let mut mesh = mesh(vec![
+            triangle((-1.0, 0.0), (0.0, 0.0), (0.0, 1.0)),
+            triangle((-1.0, 0.0), (0.0, 1.0), (-1.0, 1.0)),
+            triangle((0.0, 0.0), (1.0, 0.0), (1.0, 1.0)),
+            triangle((0.0, 0.0), (1.0, 1.0), (0.0, 1.0)),
+        ]);
+        mesh.generate_tangents();
+
+        for (index, triangle) in mesh.get_geometry().iter().enumerate() {
+            let (direction, sign) = if index < 2 { (-1.0, -1.0) } else { (1.0, 1.0) };
+            for vertex in [&triangle.a, &triangle.b, &triangle.c] {
+                // Vertices on the seam share every attribute but keep the tangent of their side
+                let tangent = vertex.tangent.unwrap();
+                assert!((tangent.xyz() - Vector3::<f32>::x() * direction).norm() < 1e-5);
+                assert_eq!(tangent.w, sign);
+
+                // The reconstructed bitangent follows increasing V on both sides
+                let bitangent = Vector3::<f32>::z().cross(&tangent.xyz()) * tangent.w;
+                assert!((bitangent - Vector3::<f32>::y()).norm() < 1e-5);
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+pub mod edge;
+#[allow(clippy::module_inception)]
 pub mod mesh;
 pub mod triangle;
 pub mod vertex;
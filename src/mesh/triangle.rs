@@ -3,21 +3,24 @@ use nalgebra::Matrix4;
 
 use super::vertex::Vertex;
 
+#[derive(Clone)]
 pub struct Triangle {
     pub a: Vertex,
     pub b: Vertex,
     pub c: Vertex,
     pub colour: Rgba<u8>,
+    pub material: usize,
 }
 
 impl Triangle {
-    ///
+    /// Transform the vertices of the triangle by `transform`
     pub fn transform(&self, transform: &Matrix4<f32>) -> Triangle {
         Triangle {
-            a: self.a.transform(&transform),
-            b: self.b.transform(&transform),
-            c: self.c.transform(&transform),
+            a: self.a.transform(transform),
+            b: self.b.transform(transform),
+            c: self.c.transform(transform),
             colour: self.colour,
+            material: self.material,
         }
     }
 }
@@ -1,15 +1,17 @@
 use image::Rgba;
-use nalgebra::{Matrix4, Point3, Vector2, Vector3};
+use nalgebra::{Matrix4, Point3, Vector2, Vector3, Vector4};
 
+#[derive(Clone)]
 pub struct Vertex {
     pub position: Point3<f32>,
     pub normal: Option<Vector3<f32>>,
+    pub tangent: Option<Vector4<f32>>,
     pub uv: Option<Vector2<f32>>,
     pub colour: Option<Rgba<u8>>,
 }
 
 impl Vertex {
-    ///
+    /// Transform the position, normal and tangent of the vertex by `transform`
     pub fn transform(&self, transform: &Matrix4<f32>) -> Vertex {
         Vertex {
             position: transform.transform_point(&self.position),
@@ -17,8 +19,40 @@ impl Vertex {
                 Some(normal) => Some(transform.transform_vector(&normal)),
                 None => None,
             },
+            tangent: self.tangent.map(|tangent| {
+                // Keep the handedness of the tangent frame in the W component
+                transform.transform_vector(&tangent.xyz()).push(tangent.w)
+            }),
             uv: self.uv,
             colour: self.colour,
         }
     }
+
+    /// Interpolate the position and attributes from this vertex towards `other`, where attributes
+    /// missing from either vertex are taken from this one
+    pub fn lerp(&self, other: &Vertex, t: f32) -> Vertex {
+        Vertex {
+            position: self.position + (other.position - self.position) * t,
+            normal: match (self.normal, other.normal) {
+                (Some(a), Some(b)) => Some(a.lerp(&b, t)),
+                (normal, _) => normal,
+            },
+            tangent: match (self.tangent, other.tangent) {
+                (Some(a), Some(b)) => Some(a.xyz().lerp(&b.xyz(), t).push(a.w)),
+                (tangent, _) => tangent,
+            },
+            uv: match (self.uv, other.uv) {
+                (Some(a), Some(b)) => Some(a.lerp(&b, t)),
+                (uv, _) => uv,
+            },
+            colour: match (self.colour, other.colour) {
+                (Some(a), Some(b)) => {
+                    Some(Rgba([0, 1, 2, 3].map(|i| {
+                        (a[i] as f32 + (b[i] as f32 - a[i] as f32) * t).round() as u8
+                    })))
+                }
+                (colour, _) => colour,
+            },
+        }
+    }
 }
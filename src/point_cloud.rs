@@ -0,0 +1,608 @@
+use image::Rgba;
+use nalgebra::{Matrix4, Point3, Vector3};
+use std::fs;
+use std::path::Path;
+
+use crate::drawable::Drawable;
+use crate::renderer::Renderer;
+use crate::transformable::Transformable;
+
+/// Shape drawn for each point, centred on its screen-space position
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum PointShape {
+    Square,
+    Disc,
+}
+
+/// A point with a colour and a size in pixels
+#[derive(Clone, Copy)]
+pub struct Point {
+    pub position: Point3<f32>,
+    pub colour: Rgba<u8>,
+    pub size: f32,
+}
+
+pub struct PointCloud {
+    points: Vec<Point>,
+    shape: PointShape,
+    transform: Matrix4<f32>,
+}
+
+impl PointCloud {
+    /// Create a cloud of points drawn with `shape`
+    pub fn new(points: Vec<Point>, shape: PointShape) -> PointCloud {
+        PointCloud {
+            points,
+            shape,
+            transform: Matrix4::<f32>::identity(),
+        }
+    }
+
+    /// Load whitespace-separated `x y z` lines, optionally followed by a colour with components
+    /// either from 0 to 1 or from 0 to 255 throughout the file
+    pub fn load_xyz<P: AsRef<Path>>(filepath: P) -> Result<PointCloud, String> {
+        let filepath = filepath.as_ref();
+        let source = fs::read_to_string(filepath)
+            .map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+
+        let mut rows = Vec::<Vec<f32>>::new();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with("//") {
+                continue;
+            }
+
+            let values = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+                .filter(|token| !token.is_empty())
+                .map(|token| token.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .ok()
+                .filter(|values| values.len() >= 3)
+                .ok_or_else(|| {
+                    format!("{}:{}: Invalid point", filepath.display(), line_number + 1)
+                })?;
+            rows.push(values);
+        }
+
+        // Colours are from 0 to 1 only if no component in the file is larger
+        let normalized = rows
+            .iter()
+            .filter(|values| values.len() >= 6)
+            .all(|values| values[3..6].iter().all(|v| *v <= 1.0));
+        let scale = if normalized { 255.0 } else { 1.0 };
+        let channel = |v: f32| (v * scale).round().clamp(0.0, 255.0) as u8;
+
+        let points = rows
+            .iter()
+            .map(|values| Point {
+                position: Point3::<f32>::new(values[0], values[1], values[2]),
+                colour: if values.len() >= 6 {
+                    Rgba([
+                        channel(values[3]),
+                        channel(values[4]),
+                        channel(values[5]),
+                        255,
+                    ])
+                } else {
+                    Rgba([255, 255, 255, 255])
+                },
+                size: 1.0,
+            })
+            .collect();
+
+        Ok(PointCloud::new(points, PointShape::Square))
+    }
+
+    /// Load the vertices of an ASCII or binary PLY file, with their colours if present
+    pub fn load_ply<P: AsRef<Path>>(filepath: P) -> Result<PointCloud, String> {
+        let filepath = filepath.as_ref();
+        let data = fs::read(filepath)
+            .map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+        let error = |message: &str| format!("Failed to load {}: {}", filepath.display(), message);
+
+        let header = PlyHeader::parse(&data).map_err(|e| error(&e))?;
+        let vertex = header
+            .elements
+            .iter()
+            .position(|element| element.name == "vertex")
+            .ok_or_else(|| error("No vertex element"))?;
+        let properties = &header.elements[vertex].properties;
+        let find = |name: &str| properties.iter().position(|p| p.name == name);
+
+        let (x, y, z) = match (find("x"), find("y"), find("z")) {
+            (Some(x), Some(y), Some(z)) => (x, y, z),
+            _ => return Err(error("Vertices have no position")),
+        };
+        let colour = [
+            find("red").or_else(|| find("r")),
+            find("green").or_else(|| find("g")),
+            find("blue").or_else(|| find("b")),
+        ];
+        let alpha = find("alpha").or_else(|| find("a"));
+
+        let mut reader = PlyReader {
+            data: &data[header.length..],
+            position: 0,
+            format: header.format,
+        };
+
+        // Skip the elements stored before the vertices
+        for element in &header.elements[..vertex] {
+            for _ in 0..element.count {
+                reader.skip_line();
+                for property in &element.properties {
+                    reader.read(property).map_err(|e| error(&e))?;
+                }
+            }
+        }
+
+        let element = &header.elements[vertex];
+        let mut points = Vec::<Point>::with_capacity(element.count);
+
+        for _ in 0..element.count {
+            reader.skip_line();
+            let values = element
+                .properties
+                .iter()
+                .map(|property| reader.read(property))
+                .collect::<Result<Vec<f32>, String>>()
+                .map_err(|e| error(&e))?;
+
+            // Integer colours run from 0 to 255, floating-point colours from 0 to 1
+            let channel = |index: Option<usize>| match index {
+                Some(index) => {
+                    let scale = if properties[index].is_float() {
+                        255.0
+                    } else {
+                        1.0
+                    };
+                    (values[index] * scale).round().clamp(0.0, 255.0) as u8
+                }
+                None => 255,
+            };
+
+            points.push(Point {
+                position: Point3::<f32>::new(values[x], values[y], values[z]),
+                colour: Rgba([
+                    channel(colour[0]),
+                    channel(colour[1]),
+                    channel(colour[2]),
+                    channel(alpha),
+                ]),
+                size: 1.0,
+            });
+        }
+
+        Ok(PointCloud::new(points, PointShape::Square))
+    }
+
+    /// Get the points of the cloud
+    #[inline]
+    pub fn get_points(&self) -> &Vec<Point> {
+        &self.points
+    }
+
+    /// Get the points of the cloud for editing
+    #[inline]
+    pub fn get_points_mut(&mut self) -> &mut Vec<Point> {
+        &mut self.points
+    }
+
+    /// Get the shape drawn for each point
+    #[inline]
+    pub fn get_shape(&self) -> PointShape {
+        self.shape
+    }
+
+    /// Set the shape drawn for each point
+    pub fn set_shape(&mut self, shape: PointShape) {
+        self.shape = shape;
+    }
+
+    /// Set the size in pixels of every point
+    pub fn set_point_size(&mut self, size: f32) {
+        for point in &mut self.points {
+            point.size = size;
+        }
+    }
+}
+
+impl Drawable for PointCloud {
+    fn draw(&self, renderer: &mut Renderer) {
+        for point in &self.points {
+            renderer.point(
+                self.transform.transform_point(&point.position),
+                point.colour,
+                point.size,
+                self.shape,
+            );
+        }
+    }
+
+    fn draw_wireframe(&self, renderer: &mut Renderer) {
+        self.draw(renderer);
+    }
+}
+
+impl Transformable for PointCloud {
+    fn translate(&mut self, delta: Vector3<f32>) {
+        self.transform *= Matrix4::<f32>::new_translation(&delta);
+    }
+
+    fn rotate(&mut self, x: f32, y: f32, z: f32) {
+        let rot_x = Vector3::x() * x;
+        let rot_y = Vector3::y() * y;
+        let rot_z = Vector3::z() * z;
+        self.transform *= Matrix4::<f32>::new_rotation(rot_x + rot_y + rot_z);
+    }
+
+    fn scale(&mut self, factor: f32) {
+        self.transform *= Matrix4::<f32>::new_scaling(factor);
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+/// A scalar or list property of a PLY element, with the sizes in bytes of its binary types
+struct PlyProperty {
+    name: String,
+    kind: String,
+    size: usize,
+    list_count_size: Option<usize>,
+}
+
+impl PlyProperty {
+    /// Check whether the property holds a floating-point value
+    fn is_float(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "float" | "float32" | "double" | "float64"
+        )
+    }
+}
+
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyProperty>,
+}
+
+struct PlyHeader {
+    format: PlyFormat,
+    elements: Vec<PlyElement>,
+    length: usize,
+}
+
+impl PlyHeader {
+    /// Parse the header at the start of a PLY file
+    fn parse(data: &[u8]) -> Result<PlyHeader, String> {
+        let type_size = |kind: &str| match kind {
+            "char" | "uchar" | "int8" | "uint8" => Ok(1),
+            "short" | "ushort" | "int16" | "uint16" => Ok(2),
+            "int" | "uint" | "float" | "int32" | "uint32" | "float32" => Ok(4),
+            "double" | "float64" => Ok(8),
+            _ => Err(format!("Unknown property type '{}'", kind)),
+        };
+
+        let mut format = None;
+        let mut elements = Vec::<PlyElement>::new();
+        let mut position = 0;
+
+        loop {
+            let end = data[position..]
+                .iter()
+                .position(|b| *b == b'\n')
+                .ok_or("Header has no end")?;
+            let line = String::from_utf8_lossy(&data[position..position + end]);
+            position += end + 1;
+
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[..] {
+                ["ply"] | ["comment", ..] | ["obj_info", ..] | [] => {}
+                ["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+                ["format", "binary_little_endian", _] => {
+                    format = Some(PlyFormat::BinaryLittleEndian)
+                }
+                ["format", "binary_big_endian", _] => format = Some(PlyFormat::BinaryBigEndian),
+                ["element", name, count] => elements.push(PlyElement {
+                    name: name.to_string(),
+                    count: count
+                        .parse()
+                        .map_err(|_| format!("Invalid element count '{}'", count))?,
+                    properties: Vec::new(),
+                }),
+                ["property", "list", count_kind, kind, name] => elements
+                    .last_mut()
+                    .ok_or("Property before any element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        size: type_size(kind)?,
+                        list_count_size: Some(type_size(count_kind)?),
+                    }),
+                ["property", kind, name] => elements
+                    .last_mut()
+                    .ok_or("Property before any element")?
+                    .properties
+                    .push(PlyProperty {
+                        name: name.to_string(),
+                        kind: kind.to_string(),
+                        size: type_size(kind)?,
+                        list_count_size: None,
+                    }),
+                ["end_header"] => break,
+                _ => return Err(format!("Invalid header line '{}'", line.trim())),
+            }
+        }
+
+        Ok(PlyHeader {
+            format: format.ok_or("No format")?,
+            elements,
+            length: position,
+        })
+    }
+}
+
+/// Reads the values of PLY properties, in either text or binary
+struct PlyReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    format: PlyFormat,
+}
+
+impl PlyReader<'_> {
+    /// Move to the start of the next line, where each ASCII element starts
+    fn skip_line(&mut self) {
+        if self.format != PlyFormat::Ascii || self.position == 0 {
+            return;
+        }
+
+        while self.position < self.data.len() && self.data[self.position - 1] != b'\n' {
+            self.position += 1;
+        }
+    }
+
+    /// Read a property, returning the first value of a list
+    fn read(&mut self, property: &PlyProperty) -> Result<f32, String> {
+        let (kind, size) = (property.kind.as_str(), property.size);
+
+        match property.list_count_size {
+            Some(count_size) => {
+                // Counts are unsigned, so reading them zero-extended suits any width
+                let count = self.value("uint", count_size)? as usize;
+                let mut first = 0.0;
+                for index in 0..count {
+                    let value = self.value(kind, size)?;
+                    if index == 0 {
+                        first = value;
+                    }
+                }
+                Ok(first)
+            }
+            None => self.value(kind, size),
+        }
+    }
+
+    /// Read the next value of type `kind`, which takes `size` bytes in a binary file
+    fn value(&mut self, kind: &str, size: usize) -> Result<f32, String> {
+        if self.format == PlyFormat::Ascii {
+            let rest = &self.data[self.position..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or("Unexpected end of file")?;
+            let length = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .unwrap_or(rest.len() - start);
+            self.position += start + length;
+
+            let token = String::from_utf8_lossy(&rest[start..start + length]);
+            return token
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid value '{}'", token));
+        }
+
+        let bytes = self
+            .data
+            .get(self.position..self.position + size)
+            .ok_or("Unexpected end of file")?;
+        self.position += size;
+
+        let mut buffer = [0_u8; 8];
+        buffer[..size].copy_from_slice(bytes);
+        if self.format == PlyFormat::BinaryBigEndian {
+            buffer[..size].reverse();
+        }
+
+        Ok(match kind {
+            "char" | "int8" => buffer[0] as i8 as f32,
+            "uchar" | "uint8" => buffer[0] as f32,
+            "short" | "int16" => i16::from_le_bytes([buffer[0], buffer[1]]) as f32,
+            "ushort" | "uint16" => u16::from_le_bytes([buffer[0], buffer[1]]) as f32,
+            "int" | "int32" => i32::from_le_bytes(buffer[..4].try_into().unwrap()) as f32,
+            "uint" | "uint32" => u32::from_le_bytes(buffer[..4].try_into().unwrap()) as f32,
+            "float" | "float32" => f32::from_le_bytes(buffer[..4].try_into().unwrap()),
+            _ => f64::from_le_bytes(buffer) as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Write a file into the temporary directory, named uniquely to this test process
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let filepath =
+            std::env::temp_dir().join(format!("minidraw_{}_{}", std::process::id(), name));
+        fs::write(&filepath, contents).unwrap();
+        filepath
+    }
+
+    /// Load an XYZ file with the given contents from the temporary directory
+    fn load_xyz_source(name: &str, source: &str) -> PointCloud {
+        let filepath = temp_file(name, source.as_bytes());
+        let cloud = PointCloud::load_xyz(&filepath);
+        fs::remove_file(&filepath).unwrap();
+        cloud.unwrap()
+    }
+
+    /// Load a PLY file with the given contents from the temporary directory
+    fn load_ply_source(name: &str, source: &[u8]) -> Result<PointCloud, String> {
+        let filepath = temp_file(name, source);
+        let cloud = PointCloud::load_ply(&filepath);
+        fs::remove_file(&filepath).unwrap();
+        cloud
+    }
+
+    /// Get the position and colour of each point
+    fn points(cloud: &PointCloud) -> Vec<([f32; 3], Rgba<u8>)> {
+        cloud
+            .get_points()
+            .iter()
+            .map(|p| (p.position.into(), p.colour))
+            .collect()
+    }
+
+    #[test]
+    fn xyz_colour_scale_is_decided_per_file() {
+        let colours = |cloud: &PointCloud| -> Vec<Rgba<u8>> {
+            cloud.get_points().iter().map(|p| p.colour).collect()
+        };
+
+        // Dark colours in a file from 0 to 255 aren't rescaled
+        let cloud = load_xyz_source(
+            "mixed.xyz",
+            "0 0 0 1 0 1\n1 0 0 200 100 50\n0 1 0 0.5 1 0\n",
+        );
+        assert_eq!(
+            colours(&cloud),
+            vec![
+                Rgba([1, 0, 1, 255]),
+                Rgba([200, 100, 50, 255]),
+                Rgba([1, 1, 0, 255]),
+            ]
+        );
+
+        let cloud = load_xyz_source("normalized.xyz", "0 0 0 1 0 1\n1 0 0 0.5 1 0\n");
+        assert_eq!(
+            colours(&cloud),
+            vec![Rgba([255, 0, 255, 255]), Rgba([128, 255, 0, 255])]
+        );
+    }
+
+    #[test]
+    fn ascii_ply_loads_vertices_and_ignores_faces() {
+        let source = "ply\n\
+            format ascii 1.0\n\
+            comment two points and a face\n\
+            element vertex 2\n\
+            property float x\n\
+            property float y\n\
+            property float z\n\
+            property uchar red\n\
+            property uchar green\n\
+            property uchar blue\n\
+            element face 1\n\
+            property list uchar int vertex_indices\n\
+            end_header\n\
+            1 2 3 255 128 0\n\
+            -1 0.5 0 0 0 255\n\
+            3 0 1 1\n";
+        let cloud = load_ply_source("ascii.ply", source.as_bytes()).unwrap();
+        assert_eq!(
+            points(&cloud),
+            vec![
+                ([1.0, 2.0, 3.0], Rgba([255, 128, 0, 255])),
+                ([-1.0, 0.5, 0.0], Rgba([0, 0, 255, 255])),
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_little_endian_ply_skips_faces_before_vertices() {
+        let mut source = b"ply\n\
+            format binary_little_endian 1.0\n\
+            element face 1\n\
+            property list uchar int vertex_indices\n\
+            element vertex 2\n\
+            property float x\n\
+            property float y\n\
+            property float z\n\
+            property float red\n\
+            property float green\n\
+            property float blue\n\
+            end_header\n"
+            .to_vec();
+        source.push(3);
+        for index in [0_i32, 1, 1] {
+            source.extend(index.to_le_bytes());
+        }
+        for value in [
+            1.0_f32, 2.0, 3.0, 1.0, 0.5, 0.0, -4.0, 5.0, -6.0, 0.0, 0.0, 1.0,
+        ] {
+            source.extend(value.to_le_bytes());
+        }
+
+        // Floating-point colours run from 0 to 1
+        let cloud = load_ply_source("little_endian.ply", &source).unwrap();
+        assert_eq!(
+            points(&cloud),
+            vec![
+                ([1.0, 2.0, 3.0], Rgba([255, 128, 0, 255])),
+                ([-4.0, 5.0, -6.0], Rgba([0, 0, 255, 255])),
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_big_endian_ply_loads_vertices() {
+        let mut source = b"ply\n\
+            format binary_big_endian 1.0\n\
+            element vertex 1\n\
+            property double x\n\
+            property float y\n\
+            property short z\n\
+            property uchar red\n\
+            property uchar green\n\
+            property uchar blue\n\
+            property uchar alpha\n\
+            end_header\n"
+            .to_vec();
+        source.extend(1.5_f64.to_be_bytes());
+        source.extend((-2.25_f32).to_be_bytes());
+        source.extend(300_i16.to_be_bytes());
+        source.extend([10, 20, 30, 40]);
+
+        let cloud = load_ply_source("big_endian.ply", &source).unwrap();
+        assert_eq!(
+            points(&cloud),
+            vec![([1.5, -2.25, 300.0], Rgba([10, 20, 30, 40]))]
+        );
+    }
+
+    #[test]
+    fn ply_without_a_complete_header_is_an_error() {
+        for (name, source) in [
+            ("empty.ply", &b""[..]),
+            (
+                "truncated.ply",
+                &b"ply\nformat ascii 1.0\nelement vertex 1\n"[..],
+            ),
+        ] {
+            let error = load_ply_source(name, source).err().unwrap();
+            assert!(error.starts_with("Failed to load "));
+            assert!(error.ends_with("Header has no end"));
+        }
+    }
+}
@@ -0,0 +1,396 @@
+use nalgebra::Vector3;
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{renderer::ColourImage, utilities};
+
+/// Weights of the Rec. 709 primaries in the luminance of a linear colour
+const LUMINANCE: Vector3<f32> = Vector3::new(0.2126, 0.7152, 0.0722);
+
+/// Weights of the primaries in the perceived brightness of an encoded colour
+const LUMA: Vector3<f32> = Vector3::new(0.299, 0.587, 0.114);
+
+/// Distances along an edge between the samples of the FXAA end search, in pixels
+const FXAA_STEPS: [f32; 12] = [1.0, 1.0, 1.0, 1.0, 1.0, 1.5, 2.0, 2.0, 2.0, 2.0, 4.0, 8.0];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    Reinhard,
+    Aces,
+}
+
+/// A step of the post-processing chain, which is applied in order to the resolved HDR image
+/// before it is quantized for output. Tone mapping and sRGB encoding bring colours into the
+/// display range, and FXAA and colour grading expect to follow them. The image is encoded to sRGB
+/// after the last step unless the chain includes `SrgbEncode`
+#[derive(Clone)]
+pub enum PostEffect {
+    /// Scale colours by two to the power of a number of stops
+    Exposure(f32),
+    ToneMap(ToneMapping),
+    SrgbEncode,
+    /// Add a blur of the parts of the image brighter than `threshold`, with a radius in pixels
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: u32,
+    },
+    Fxaa,
+    /// Darken towards the corners, starting at `radius` as a fraction of the distance from the
+    /// centre to the corners
+    Vignette {
+        intensity: f32,
+        radius: f32,
+    },
+    ColourGrading(Arc<ColourLut>),
+}
+
+/// A 3D colour lookup table, mapping colours in the unit cube to graded colours
+pub struct ColourLut {
+    size: usize,
+    domain_min: Vector3<f32>,
+    domain_max: Vector3<f32>,
+    table: Vec<Vector3<f32>>,
+}
+
+impl ColourLut {
+    /// Create a table of `size` entries on each axis by evaluating `grade` at each of them
+    pub fn from_fn<F>(size: usize, grade: F) -> ColourLut
+    where
+        F: Fn(Vector3<f32>) -> Vector3<f32>,
+    {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let table = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, (i / size) % size, i / (size * size));
+                grade(Vector3::<f32>::new(r as f32, g as f32, b as f32) * step)
+            })
+            .collect();
+
+        ColourLut {
+            size,
+            domain_min: Vector3::<f32>::zeros(),
+            domain_max: Vector3::<f32>::repeat(1.0),
+            table,
+        }
+    }
+
+    /// Create a table which leaves colours unchanged
+    pub fn identity(size: usize) -> ColourLut {
+        ColourLut::from_fn(size, |colour| colour)
+    }
+
+    /// Load a 3D table from an Adobe/Resolve `.cube` file
+    pub fn load_cube<P: AsRef<Path>>(filepath: P) -> Result<ColourLut, String> {
+        let filepath = filepath.as_ref();
+        let contents = std::fs::read_to_string(filepath)
+            .map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+        let invalid = |line: &str| format!("Invalid line in {}: {}", filepath.display(), line);
+        let parse_vector = |values: &[&str], line: &str| -> Result<Vector3<f32>, String> {
+            let values: Vec<f32> = values
+                .iter()
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(line))?;
+            match values[..] {
+                [r, g, b] => Ok(Vector3::<f32>::new(r, g, b)),
+                _ => Err(invalid(line)),
+            }
+        };
+
+        let mut size = 0;
+        let mut domain_min = Vector3::<f32>::zeros();
+        let mut domain_max = Vector3::<f32>::repeat(1.0);
+        let mut table = Vec::new();
+
+        for line in contents.lines().map(str::trim) {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            match tokens[..] {
+                [] => {}
+                [first, ..] if first.starts_with('#') || first == "TITLE" => {}
+                ["LUT_3D_SIZE", value] => {
+                    size = value.parse::<usize>().map_err(|_| invalid(line))?;
+                }
+                ["LUT_1D_SIZE", _] => {
+                    return Err(format!("Unsupported 1D table in {}", filepath.display()));
+                }
+                ["DOMAIN_MIN", ..] => domain_min = parse_vector(&tokens[1..], line)?,
+                ["DOMAIN_MAX", ..] => domain_max = parse_vector(&tokens[1..], line)?,
+                _ => table.push(parse_vector(&tokens, line)?),
+            }
+        }
+
+        if size < 2 || table.len() != size * size * size {
+            return Err(format!(
+                "Expected {} entries in {}, found {}",
+                size * size * size,
+                filepath.display(),
+                table.len()
+            ));
+        }
+
+        Ok(ColourLut {
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Look up a colour, interpolating between the surrounding entries
+    pub fn sample(&self, colour: &Vector3<f32>) -> Vector3<f32> {
+        let extent = (self.domain_max - self.domain_min).map(|e| e.max(f32::EPSILON));
+        let position = (colour - self.domain_min)
+            .component_div(&extent)
+            .map(|c| c.clamp(0.0, 1.0) * (self.size - 1) as f32);
+        let lower = position.map(|c| (c.floor() as usize).min(self.size - 2));
+        let t = position - lower.map(|c| c as f32);
+
+        let entry = |r: usize, g: usize, b: usize| {
+            self.table[((lower.z + b) * self.size + lower.y + g) * self.size + lower.x + r]
+        };
+        let lerp_r = |g, b| entry(0, g, b).lerp(&entry(1, g, b), t.x);
+        let lerp_g = |b| lerp_r(0, b).lerp(&lerp_r(1, b), t.y);
+        lerp_g(0).lerp(&lerp_g(1), t.z)
+    }
+}
+
+/// Apply a chain of effects to an image in order
+pub fn apply(effects: &[PostEffect], image: &mut ColourImage) {
+    for effect in effects {
+        match effect {
+            PostEffect::Exposure(stops) => {
+                let scale = 2.0_f32.powf(*stops);
+                map_colours(image, |colour| colour * scale);
+            }
+            PostEffect::ToneMap(ToneMapping::Reinhard) => {
+                map_colours(image, |colour| colour.map(|c| c / (1.0 + c)));
+            }
+            PostEffect::ToneMap(ToneMapping::Aces) => {
+                // Narkowicz's fit of the ACES filmic curve
+                map_colours(image, |colour| {
+                    colour.map(|c| {
+                        (c * (2.51 * c + 0.03) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0)
+                    })
+                });
+            }
+            PostEffect::SrgbEncode => {
+                map_colours(image, |colour| colour.map(utilities::linear_to_srgb));
+            }
+            PostEffect::Bloom {
+                threshold,
+                intensity,
+                radius,
+            } => bloom(image, *threshold, *intensity, *radius),
+            PostEffect::Fxaa => fxaa(image),
+            PostEffect::Vignette { intensity, radius } => vignette(image, *intensity, *radius),
+            PostEffect::ColourGrading(lut) => map_colours(image, |colour| lut.sample(&colour)),
+        }
+    }
+}
+
+/// Replace the colour of each pixel, keeping its alpha
+fn map_colours<F>(image: &mut ColourImage, f: F)
+where
+    F: Fn(Vector3<f32>) -> Vector3<f32> + Sync,
+{
+    image.par_chunks_mut(4).for_each(|pixel| {
+        let colour = f(Vector3::<f32>::new(pixel[0], pixel[1], pixel[2]));
+        pixel[..3].copy_from_slice(colour.as_slice());
+    });
+}
+
+/// Get the colour of each pixel of an image
+fn colours(image: &ColourImage) -> Vec<Vector3<f32>> {
+    image
+        .chunks(4)
+        .map(|pixel| Vector3::<f32>::new(pixel[0], pixel[1], pixel[2]))
+        .collect()
+}
+
+/// Darken the image towards its corners, starting at `radius` from the centre
+fn vignette(image: &mut ColourImage, intensity: f32, radius: f32) {
+    let (width, height) = image.dimensions();
+    let centre = Vector3::<f32>::new(width as f32 / 2.0, height as f32 / 2.0, 0.0);
+
+    image.par_chunks_mut(4).enumerate().for_each(|(i, pixel)| {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let offset = Vector3::<f32>::new(x as f32 + 0.5, y as f32 + 0.5, 0.0) - centre;
+        let distance = offset.norm() / centre.norm();
+
+        let t = ((distance - radius) / (1.0 - radius).max(f32::EPSILON)).clamp(0.0, 1.0);
+        let factor = 1.0 - intensity * t * t * (3.0 - 2.0 * t);
+        for channel in &mut pixel[..3] {
+            *channel *= factor;
+        }
+    });
+}
+
+/// Add a Gaussian blur of the bright parts of an image to it, so they glow
+fn bloom(image: &mut ColourImage, threshold: f32, intensity: f32, radius: u32) {
+    let (width, height) = image.dimensions();
+
+    // Keep the part of each colour's luminance above the threshold
+    let bright: Vec<Vector3<f32>> = colours(image)
+        .into_iter()
+        .map(|colour| {
+            let luminance = colour.dot(&LUMINANCE);
+            if luminance <= threshold {
+                Vector3::<f32>::zeros()
+            } else {
+                colour * ((luminance - threshold) / luminance)
+            }
+        })
+        .collect();
+
+    let radius = radius.max(1) as i64;
+    let sigma = radius as f32 / 2.0;
+    let weights: Vec<f32> = (-radius..=radius)
+        .map(|offset| (-(offset * offset) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f32 = weights.iter().sum();
+
+    let pass = |values: &[Vector3<f32>], (dx, dy): (i64, i64)| -> Vec<Vector3<f32>> {
+        (0..values.len())
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = ((i as u32 % width) as i64, (i as u32 / width) as i64);
+                let mut sum = Vector3::<f32>::zeros();
+                for (offset, weight) in (-radius..=radius).zip(&weights) {
+                    // Clamp to the edge of the image
+                    let u = (x + dx * offset).clamp(0, width as i64 - 1);
+                    let v = (y + dy * offset).clamp(0, height as i64 - 1);
+                    sum += values[(v * width as i64 + u) as usize] * *weight;
+                }
+                sum / total
+            })
+            .collect()
+    };
+    let blurred = pass(&pass(&bright, (1, 0)), (0, 1));
+
+    image
+        .par_chunks_mut(4)
+        .zip(blurred.par_iter())
+        .for_each(|(pixel, glow)| {
+            for channel in 0..3 {
+                pixel[channel] += glow[channel] * intensity;
+            }
+        });
+}
+
+/// Smooth jagged edges with fast approximate anti-aliasing. Edges are found from the contrast in
+/// luma between neighbouring pixels, then each pixel on an edge is blended with its neighbour
+/// across the edge by how far it is from the ends of the edge
+fn fxaa(image: &mut ColourImage) {
+    let (width, height) = image.dimensions();
+    let source = colours(image);
+    let luma: Vec<f32> = source
+        .iter()
+        .map(|colour| colour.map(|c| c.clamp(0.0, 1.0)).dot(&LUMA))
+        .collect();
+
+    let at = |x: i64, y: i64| {
+        let (x, y) = (x.clamp(0, width as i64 - 1), y.clamp(0, height as i64 - 1));
+        (y * width as i64 + x) as usize
+    };
+    // Bilinearly sample values at a position in pixels, with pixel centres at half pixels
+    let sample = |x: f32, y: f32, f: &dyn Fn(usize) -> Vector3<f32>| {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (left, top) = (x.floor() as i64, y.floor() as i64);
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+        let upper = f(at(left, top)).lerp(&f(at(left + 1, top)), tx);
+        let lower = f(at(left, top + 1)).lerp(&f(at(left + 1, top + 1)), tx);
+        upper.lerp(&lower, ty)
+    };
+    let sample_luma = |x: f32, y: f32| sample(x, y, &|i| Vector3::<f32>::repeat(luma[i])).x;
+
+    image.par_chunks_mut(4).enumerate().for_each(|(i, pixel)| {
+        let (x, y) = ((i as u32 % width) as i64, (i as u32 / width) as i64);
+        let m = luma[i];
+        let (n, s) = (luma[at(x, y - 1)], luma[at(x, y + 1)]);
+        let (w, e) = (luma[at(x - 1, y)], luma[at(x + 1, y)]);
+
+        // Skip pixels without enough local contrast to be on an edge
+        let max = m.max(n).max(s).max(w).max(e);
+        let min = m.min(n).min(s).min(w).min(e);
+        let range = max - min;
+        if range < (max * 0.125).max(0.0312) {
+            return;
+        }
+
+        let (nw, ne) = (luma[at(x - 1, y - 1)], luma[at(x + 1, y - 1)]);
+        let (sw, se) = (luma[at(x - 1, y + 1)], luma[at(x + 1, y + 1)]);
+
+        // Blend isolated pixels towards their neighbourhood
+        let average = (2.0 * (n + s + w + e) + nw + ne + sw + se) / 12.0;
+        let subpixel = ((average - m).abs() / range).clamp(0.0, 1.0);
+        let subpixel = subpixel * subpixel * (3.0 - 2.0 * subpixel);
+        let subpixel = subpixel * subpixel * 0.75;
+
+        // Find whether the edge runs horizontally or vertically, and which side of this
+        // pixel it is on
+        let horizontal =
+            (nw + ne - 2.0 * n).abs() + 2.0 * (w + e - 2.0 * m).abs() + (sw + se - 2.0 * s).abs();
+        let vertical =
+            (nw + sw - 2.0 * w).abs() + 2.0 * (n + s - 2.0 * m).abs() + (ne + se - 2.0 * e).abs();
+        let horizontal = horizontal >= vertical;
+        let (along, across) = if horizontal {
+            ((1.0, 0.0), (0.0, 1.0))
+        } else {
+            ((0.0, 1.0), (1.0, 0.0))
+        };
+        let (before, after) = if horizontal { (n, s) } else { (w, e) };
+        let (side, opposite) = if (after - m).abs() >= (before - m).abs() {
+            (1.0, after)
+        } else {
+            (-1.0, before)
+        };
+        let gradient = (opposite - m).abs() * 0.25;
+        let edge_luma = (m + opposite) / 2.0;
+
+        // Search along the edge in both directions for where it ends
+        let (cx, cy) = (
+            x as f32 + 0.5 + across.0 * side * 0.5,
+            y as f32 + 0.5 + across.1 * side * 0.5,
+        );
+        let search = |direction: f32| {
+            let mut distance = 0.0;
+            let mut delta = 0.0;
+            for step in FXAA_STEPS {
+                distance += step;
+                delta = sample_luma(
+                    cx + along.0 * direction * distance,
+                    cy + along.1 * direction * distance,
+                ) - edge_luma;
+                if delta.abs() >= gradient {
+                    break;
+                }
+            }
+            (distance, delta)
+        };
+        let (positive, positive_delta) = search(1.0);
+        let (negative, negative_delta) = search(-1.0);
+
+        // Only blend when the nearer end bends away from this pixel's side of the edge
+        let (nearest, delta) = if positive < negative {
+            (positive, positive_delta)
+        } else {
+            (negative, negative_delta)
+        };
+        let edge_offset = if (delta < 0.0) == (m - edge_luma < 0.0) {
+            0.0
+        } else {
+            0.5 - nearest / (positive + negative)
+        };
+
+        let offset = edge_offset.max(subpixel) * side;
+        let colour = sample(
+            x as f32 + 0.5 + across.0 * offset,
+            y as f32 + 0.5 + across.1 * offset,
+            &|i| source[i],
+        );
+        pixel[..3].copy_from_slice(colour.as_slice());
+    });
+}
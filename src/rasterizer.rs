@@ -0,0 +1,855 @@
+use nalgebra::{Point4, Vector2, Vector3};
+use std::ops::Range;
+use wide::{f32x4, i64x4};
+
+use crate::renderer_config::DepthCompare;
+
+/// Number of horizontally adjacent pixels whose edge functions are evaluated together
+const LANES: usize = 4;
+
+/// Width and height in pixels of each block of the hierarchical depth buffer
+pub const BLOCK_SIZE: u32 = 8;
+
+/// Number of fractional bits in fixed-point screen-space coordinates
+const SUBPIXEL_BITS: u32 = 8;
+const SUBPIXEL_SCALE: f32 = (1 << SUBPIXEL_BITS) as f32;
+const SUBPIXEL_HALF: i64 = 1 << (SUBPIXEL_BITS - 1);
+
+/// Largest fixed-point coordinate magnitude, keeping edge function products within an i64
+const COORDINATE_LIMIT: i64 = 1 << 28;
+
+/// Signed area test of a point against a directed triangle edge, in fixed-point units
+struct EdgeFunction {
+    a: i64,
+    b: i64,
+    c: i64,
+    bias: i64,
+}
+
+impl EdgeFunction {
+    /// Create the edge function of the edge from `v0` to `v1`, with its fill rule bias
+    fn new(v0: &Vector2<i64>, v1: &Vector2<i64>) -> EdgeFunction {
+        let a = v0.y - v1.y;
+        let b = v1.x - v0.x;
+
+        // Top-left rule: samples exactly on an edge belong to the triangle only if the edge is
+        // a top edge or a left edge, so shared edges are drawn exactly once
+        let top = a == 0 && b > 0;
+        let left = a > 0;
+
+        EdgeFunction {
+            a,
+            b,
+            c: v0.x * v1.y - v0.y * v1.x,
+            bias: if top || left { 0 } else { -1 },
+        }
+    }
+
+    /// Evaluate the edge function at a fixed-point position
+    #[inline]
+    fn evaluate(&self, x: i64, y: i64) -> i64 {
+        self.a * x + self.b * y + self.c
+    }
+}
+
+/// Convert a screen-space position into fixed-point coordinates
+#[inline]
+fn to_fixed(p: &Point4<f32>) -> Vector2<i64> {
+    Vector2::<i64>::new(
+        ((p.x * SUBPIXEL_SCALE).round() as i64).clamp(-COORDINATE_LIMIT, COORDINATE_LIMIT),
+        ((p.y * SUBPIXEL_SCALE).round() as i64).clamp(-COORDINATE_LIMIT, COORDINATE_LIMIT),
+    )
+}
+
+/// Smallest and largest depth within an area
+#[derive(Clone, Copy)]
+pub struct DepthRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl DepthRange {
+    /// Create a range containing only `depth`
+    pub fn new(depth: f32) -> DepthRange {
+        DepthRange {
+            min: depth,
+            max: depth,
+        }
+    }
+}
+
+/// Rows of a depth buffer holding several samples for each pixel, with an optional hierarchical
+/// depth buffer holding the depth range of each block of pixels. If `reciprocal_depth` is set,
+/// the depth of each vertex is the reciprocal of the depth to store
+pub struct DepthTarget<'a> {
+    pub depth_buffer: &'a mut [f32],
+    pub hierarchical_depth: Option<&'a mut [DepthRange]>,
+    pub width: u32,
+    pub rows: Range<u32>,
+    pub reciprocal_depth: bool,
+    pub scratch: RasterScratch,
+}
+
+/// Working buffers of the rasterizer, kept with a target so triangles don't allocate their own
+pub struct RasterScratch {
+    sample_steps: Vec<[i64x4; 3]>,
+    occluded: Vec<bool>,
+    written: Vec<bool>,
+}
+
+impl RasterScratch {
+    /// Create empty buffers, which grow to suit the triangles rasterized
+    pub fn default() -> RasterScratch {
+        RasterScratch {
+            sample_steps: Vec::new(),
+            occluded: Vec::new(),
+            written: Vec::new(),
+        }
+    }
+}
+
+/// Get the number of blocks covering a number of pixels
+#[inline]
+pub fn block_count(pixels: u32) -> u32 {
+    pixels.div_ceil(BLOCK_SIZE)
+}
+
+/// Rasterize a screen-space triangle against a depth target holding `sample_offsets.len()`
+/// samples per pixel. Samples are taken at pixel centres plus each offset. `fragment` is called
+/// with the pixel coordinates, the barycentric coordinates of the first covered sample and a mask
+/// of the samples which passed the depth test. `depth_bias` is added to the depth of each sample
+pub fn rasterize<F: FnMut(u32, u32, Vector3<f32>, u32)>(
+    target: &mut DepthTarget,
+    points: &[Point4<f32>; 3],
+    sample_offsets: &[Vector2<f32>],
+    depth_compare: DepthCompare,
+    depth_write: bool,
+    depth_bias: f32,
+    mut fragment: F,
+) {
+    let samples = sample_offsets.len() as u32;
+    let width = target.width;
+    let rows = target.rows.clone();
+    let buffer_width = width * samples;
+    let reciprocal_depth = target.reciprocal_depth;
+    let depth_buffer = &mut *target.depth_buffer;
+    let RasterScratch {
+        sample_steps,
+        occluded,
+        written,
+    } = &mut target.scratch;
+
+    // Snap the vertices to the sub-pixel grid
+    let mut vertices = points.map(|p| to_fixed(&p));
+    let mut order = [0, 1, 2];
+
+    // Rasterize both windings with a positive area, remembering the original vertex order
+    let area = EdgeFunction::new(&vertices[0], &vertices[1]).evaluate(vertices[2].x, vertices[2].y);
+    if area == 0 {
+        return;
+    }
+    if area < 0 {
+        vertices.swap(1, 2);
+        order.swap(1, 2);
+    }
+    let area = area.abs() as f32;
+
+    // Edge opposite each vertex
+    let edges = [
+        EdgeFunction::new(&vertices[1], &vertices[2]),
+        EdgeFunction::new(&vertices[2], &vertices[0]),
+        EdgeFunction::new(&vertices[0], &vertices[1]),
+    ];
+
+    // Find the pixel bounding box of this triangle, including samples offset into neighbouring
+    // pixels
+    let margin = if samples > 1 { 1 } else { 0 };
+    let min_x = (vertices.iter().map(|v| v.x).min().unwrap() >> SUBPIXEL_BITS) - margin;
+    let min_y = (vertices.iter().map(|v| v.y).min().unwrap() >> SUBPIXEL_BITS) - margin;
+    let max_x = (vertices.iter().map(|v| v.x).max().unwrap() >> SUBPIXEL_BITS) + margin;
+    let max_y = (vertices.iter().map(|v| v.y).max().unwrap() >> SUBPIXEL_BITS) + margin;
+
+    // Start each row on a multiple of the lane count so that groups of pixels never straddle
+    // blocks
+    let min_x = min_x.max(0) & !(LANES as i64 - 1);
+    let min_y = min_y.max(rows.start as i64);
+    let max_x = max_x.min(width as i64 - 1);
+    let max_y = max_y.min(rows.end as i64 - 1);
+    if min_x > max_x || min_y > max_y {
+        return;
+    }
+
+    // Bound the biased depth of the triangle, allowing for rounding in the interpolation
+    let vertex_depths = points.map(|p| if reciprocal_depth { 1.0 / p.z } else { p.z });
+    let magnitude = vertex_depths.iter().map(|d| d.abs()).fold(0.0, f32::max);
+    let margin = 8.0 * f32::EPSILON * magnitude;
+    let triangle_depth = DepthRange {
+        min: vertex_depths.iter().fold(f32::INFINITY, |a, b| a.min(*b)) + depth_bias - margin,
+        max: vertex_depths
+            .iter()
+            .fold(f32::NEG_INFINITY, |a, b| a.max(*b))
+            + depth_bias
+            + margin,
+    };
+
+    // Change in each edge function from the pixel centre to each sample, using the fixed-point
+    // offset of the sample
+    sample_steps.clear();
+    sample_steps.extend(sample_offsets.iter().map(|o| {
+        let (x, y) = (
+            (o.x * SUBPIXEL_SCALE).round() as i64,
+            (o.y * SUBPIXEL_SCALE).round() as i64,
+        );
+        edges.each_ref().map(|e| i64x4::splat(e.a * x + e.b * y))
+    }));
+
+    let depths = order.map(|i| f32x4::splat(points[i].z));
+    let area = f32x4::splat(area);
+    let biases = edges.each_ref().map(|e| i64x4::splat(e.bias));
+    let step = 1 << SUBPIXEL_BITS;
+
+    // Change in each edge function between groups of pixels
+    let lane_steps = edges
+        .each_ref()
+        .map(|e| i64x4::splat(e.a * step * LANES as i64));
+
+    let blocks_x = block_count(width) as usize;
+    let first_block = (min_x as u32 / BLOCK_SIZE) as usize;
+    let last_block = (max_x as u32 / BLOCK_SIZE) as usize;
+    occluded.clear();
+    occluded.resize(last_block - first_block + 1, false);
+    written.clear();
+    written.resize(last_block - first_block + 1, false);
+
+    // Render each row of blocks
+    let mut block_y = min_y;
+    while block_y <= max_y {
+        let block_row = (block_y as u32 - rows.start) / BLOCK_SIZE;
+        let block_end = (rows.start + (block_row + 1) * BLOCK_SIZE).min(rows.end) as i64;
+
+        // Reject blocks where the whole triangle fails the depth test
+        if let Some(hierarchical_depth) = &target.hierarchical_depth {
+            for (block, occluded) in occluded.iter_mut().enumerate() {
+                let block_depth =
+                    &hierarchical_depth[block_row as usize * blocks_x + first_block + block];
+                *occluded = depth_compare.rejects(&triangle_depth, block_depth);
+            }
+            if occluded.iter().all(|o| *o) {
+                block_y = block_end;
+                continue;
+            }
+        }
+
+        for y in block_y..block_end.min(max_y + 1) {
+            let centre_y = (y << SUBPIXEL_BITS) + SUBPIXEL_HALF;
+            let centre_x = (min_x << SUBPIXEL_BITS) + SUBPIXEL_HALF;
+
+            // Edge functions at the centres of the first group of pixels in this row
+            let mut row = edges.each_ref().map(|e| {
+                let value = e.evaluate(centre_x, centre_y);
+                i64x4::new([0, 1, 2, 3].map(|lane| value + e.a * step * lane))
+            });
+
+            for x in (min_x..=max_x).step_by(LANES) {
+                let block = x as usize / BLOCK_SIZE as usize - first_block;
+                if occluded[block] {
+                    for (value, lane_step) in row.iter_mut().zip(lane_steps.iter()) {
+                        *value = *value + *lane_step;
+                    }
+                    continue;
+                }
+
+                // Ignore lanes past the end of the row
+                let lanes = ((max_x - x + 1) as usize).min(LANES);
+
+                let mut coverage = [0u32; LANES];
+                let mut covered = [None; LANES];
+
+                for (sample, sample_step) in sample_steps.iter().enumerate() {
+                    let e = [0, 1, 2].map(|i| row[i] + sample_step[i]);
+
+                    // A sample is inside the triangle if no biased edge function is negative
+                    let outside = (e[0] + biases[0]) | (e[1] + biases[1]) | (e[2] + biases[2]);
+                    let inside = !outside.move_mask() & ((1 << lanes) - 1);
+                    if inside == 0 {
+                        continue;
+                    }
+
+                    // Calculate the depth, which is affine in screen-space
+                    let b = e.map(|e| f32x4::from(e.to_array().map(|v| v as f32)) / area);
+                    let depth = b[0] * depths[0] + b[1] * depths[1] + b[2] * depths[2];
+                    let depth = if reciprocal_depth {
+                        f32x4::splat(1.0) / depth
+                    } else {
+                        depth
+                    };
+                    let depth = (depth + f32x4::splat(depth_bias)).to_array();
+                    let b = b.map(|b| b.to_array());
+
+                    for lane in (0..lanes).filter(|lane| inside & (1 << lane) != 0) {
+                        // Set sample
+                        let index = ((y as u32 - rows.start) * buffer_width
+                            + (x as u32 + lane as u32) * samples
+                            + sample as u32) as usize;
+
+                        if depth_compare.passes(depth[lane], depth_buffer[index]) {
+                            if depth_write {
+                                depth_buffer[index] = depth[lane];
+                                written[block] = true;
+                            }
+                            coverage[lane] |= 1 << sample;
+                            covered[lane].get_or_insert([b[0][lane], b[1][lane], b[2][lane]]);
+                        }
+                    }
+                }
+
+                for lane in 0..lanes {
+                    if let Some(b) = covered[lane] {
+                        // Return the barycentric coordinates in the original vertex order
+                        let mut original = Vector3::<f32>::zeros();
+                        for (i, vertex) in order.iter().enumerate() {
+                            original[*vertex] = b[i];
+                        }
+                        fragment(x as u32 + lane as u32, y as u32, original, coverage[lane]);
+                    }
+                }
+
+                for (value, lane_step) in row.iter_mut().zip(lane_steps.iter()) {
+                    *value = *value + *lane_step;
+                }
+            }
+        }
+
+        // Refresh the depth range of each block which was written to
+        if let Some(hierarchical_depth) = &mut target.hierarchical_depth {
+            let first_row = block_row * BLOCK_SIZE;
+            let last_row = (first_row + BLOCK_SIZE).min(rows.end - rows.start);
+
+            for (block, written) in written.iter_mut().enumerate() {
+                if !std::mem::take(written) {
+                    continue;
+                }
+
+                let block_x = (first_block + block) as u32;
+                let first_column = block_x * BLOCK_SIZE * samples;
+                let last_column = ((block_x + 1) * BLOCK_SIZE).min(width) * samples;
+
+                let range = (first_row..last_row)
+                    .flat_map(|v| {
+                        let start = (v * buffer_width) as usize;
+                        &depth_buffer[start + first_column as usize..start + last_column as usize]
+                    })
+                    .fold(
+                        DepthRange {
+                            min: f32::INFINITY,
+                            max: f32::NEG_INFINITY,
+                        },
+                        |range, depth| DepthRange {
+                            min: range.min.min(*depth),
+                            max: range.max.max(*depth),
+                        },
+                    );
+                hierarchical_depth[block_row as usize * blocks_x + block_x as usize] = range;
+            }
+        }
+
+        block_y = block_end;
+    }
+}
+
+/// Find the range of positions along the segment from `p0` to `p1`, from 0 to 1, which lies
+/// between the corners `min` and `max` of a rectangle
+fn clip_segment(
+    p0: Vector2<f32>,
+    p1: Vector2<f32>,
+    min: Vector2<f32>,
+    max: Vector2<f32>,
+) -> Option<(f32, f32)> {
+    let delta = p1 - p0;
+    let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+
+    for axis in 0..2 {
+        // Distance inside each boundary at the start, and its rate of decrease along the segment
+        for (inside, rate) in [
+            (p0[axis] - min[axis], -delta[axis]),
+            (max[axis] - p0[axis], delta[axis]),
+        ] {
+            if rate == 0.0 {
+                if inside < 0.0 {
+                    return None;
+                }
+            } else if rate < 0.0 {
+                t0 = t0.max(inside / rate);
+            } else {
+                t1 = t1.min(inside / rate);
+            }
+        }
+    }
+
+    // Also rejects non-finite end points
+    if t0 <= t1 {
+        Some((t0, t1))
+    } else {
+        None
+    }
+}
+
+/// Rasterize a screen-space line segment from `p0` to `p1`, `line_width` pixels wide, into a
+/// target of `width` by `height` pixels. The segment is clipped to the target before stepping
+/// along it. `fragment` is called with the pixel coordinates, the position of the pixel along the
+/// segment from 0 to 1 and the fraction of the pixel which the line covers
+pub fn rasterize_line<F: FnMut(u32, u32, f32, f32)>(
+    p0: Vector2<f32>,
+    p1: Vector2<f32>,
+    line_width: f32,
+    anti_aliased: bool,
+    width: u32,
+    height: u32,
+    mut fragment: F,
+) {
+    let delta = p1 - p0;
+    let length = delta.norm();
+    let direction = if length > 0.0 {
+        delta / length
+    } else {
+        Vector2::<f32>::x()
+    };
+
+    // Aliased lines are at least one pixel wide, so they never have gaps
+    let half_width = if anti_aliased {
+        line_width / 2.0
+    } else {
+        line_width.max(1.0) / 2.0
+    };
+    let margin = half_width + 1.0;
+
+    let (t0, t1) = match clip_segment(
+        p0,
+        p1,
+        Vector2::<f32>::new(-margin, -margin),
+        Vector2::<f32>::new(width as f32 + margin, height as f32 + margin),
+    ) {
+        Some(range) => range,
+        None => return,
+    };
+
+    // Step along the major axis, one row or column of pixels at a time
+    let steep = delta.y.abs() > delta.x.abs();
+    let swizzle = |v: Vector2<f32>| {
+        if steep {
+            Vector2::<f32>::new(v.y, v.x)
+        } else {
+            v
+        }
+    };
+    let (major_size, minor_size) = if steep {
+        (height as i64, width as i64)
+    } else {
+        (width as i64, height as i64)
+    };
+
+    let start = swizzle(p0);
+    let step = swizzle(delta);
+    let slope = if step.x != 0.0 { step.y / step.x } else { 0.0 };
+    let a0 = start.x + step.x * t0;
+    let a1 = start.x + step.x * t1;
+    let (a_min, a_max) = (a0.min(a1), a0.max(a1));
+
+    // Aliased lines cover the pixels containing their end points, and anti-aliased lines also
+    // cover pixels partly overlapped by their width
+    let (first, last) = if anti_aliased {
+        ((a_min - margin).floor(), (a_max + margin).ceil())
+    } else {
+        (a_min.floor(), a_max.floor())
+    };
+    let first = (first as i64).max(0);
+    let last = (last as i64).min(major_size - 1);
+    let minor_extent = half_width * (1.0 + slope * slope).sqrt() + 1.0;
+
+    for a in first..=last {
+        let centre = start.y + slope * (a as f32 + 0.5 - start.x);
+
+        // Aliased lines cover the pixels whose centres are within half the width along the minor
+        // axis
+        let (b_first, b_last) = if anti_aliased {
+            (
+                (centre - minor_extent).floor(),
+                (centre + minor_extent).ceil(),
+            )
+        } else {
+            (
+                (centre - half_width - 0.5).ceil(),
+                (centre + half_width - 0.5).ceil() - 1.0,
+            )
+        };
+        let b_first = (b_first as i64).max(0);
+        let b_last = (b_last as i64).min(minor_size - 1);
+
+        for b in b_first..=b_last {
+            let (x, y) = if steep { (b, a) } else { (a, b) };
+            let offset = Vector2::<f32>::new(x as f32 + 0.5, y as f32 + 0.5) - p0;
+            let along = offset.dot(&direction);
+
+            // Approximate coverage from the distances of the pixel centre to the sides and ends
+            let coverage = if anti_aliased {
+                let across = offset.perp(&direction).abs();
+                let sides = (half_width + 0.5 - across).clamp(0.0, 1.0);
+                let ends = (along + 0.5).clamp(0.0, 1.0) * (length - along + 0.5).clamp(0.0, 1.0);
+                sides * ends
+            } else {
+                1.0
+            };
+            if coverage <= 0.0 {
+                continue;
+            }
+
+            let t = if length > 0.0 {
+                (along / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            fragment(x as u32, y as u32, t, coverage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderer_config::{AntiAliasing, SampleCount};
+    use nalgebra::Point4;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    type Fragment = (u32, u32, Vector3<f32>, u32);
+
+    /// Rasterize like `rasterize`, but one sample at a time over every pixel of the target and
+    /// without the hierarchical depth buffer
+    fn rasterize_reference<F: FnMut(u32, u32, Vector3<f32>, u32)>(
+        target: &mut DepthTarget,
+        points: &[Point4<f32>; 3],
+        sample_offsets: &[Vector2<f32>],
+        depth_compare: DepthCompare,
+        depth_write: bool,
+        depth_bias: f32,
+        mut fragment: F,
+    ) {
+        let samples = sample_offsets.len() as u32;
+        let buffer_width = target.width * samples;
+
+        let mut vertices = points.map(|p| to_fixed(&p));
+        let mut order = [0, 1, 2];
+        let area =
+            EdgeFunction::new(&vertices[0], &vertices[1]).evaluate(vertices[2].x, vertices[2].y);
+        if area == 0 {
+            return;
+        }
+        if area < 0 {
+            vertices.swap(1, 2);
+            order.swap(1, 2);
+        }
+        let area = area.abs() as f32;
+        let edges = [
+            EdgeFunction::new(&vertices[1], &vertices[2]),
+            EdgeFunction::new(&vertices[2], &vertices[0]),
+            EdgeFunction::new(&vertices[0], &vertices[1]),
+        ];
+        let depths = order.map(|i| points[i].z);
+
+        for y in target.rows.clone() {
+            for x in 0..target.width {
+                let mut coverage = 0;
+                let mut covered = None;
+
+                for (sample, offset) in sample_offsets.iter().enumerate() {
+                    let sample_x = ((x as i64) << SUBPIXEL_BITS)
+                        + SUBPIXEL_HALF
+                        + (offset.x * SUBPIXEL_SCALE).round() as i64;
+                    let sample_y = ((y as i64) << SUBPIXEL_BITS)
+                        + SUBPIXEL_HALF
+                        + (offset.y * SUBPIXEL_SCALE).round() as i64;
+                    let e = edges.each_ref().map(|e| e.evaluate(sample_x, sample_y));
+                    if e.iter()
+                        .zip(&edges)
+                        .any(|(value, edge)| value + edge.bias < 0)
+                    {
+                        continue;
+                    }
+
+                    let b = e.map(|e| e as f32 / area);
+                    let depth = b[0] * depths[0] + b[1] * depths[1] + b[2] * depths[2];
+                    let depth = if target.reciprocal_depth {
+                        1.0 / depth
+                    } else {
+                        depth
+                    };
+                    let depth = depth + depth_bias;
+
+                    let index = ((y - target.rows.start) * buffer_width
+                        + x * samples
+                        + sample as u32) as usize;
+                    if depth_compare.passes(depth, target.depth_buffer[index]) {
+                        if depth_write {
+                            target.depth_buffer[index] = depth;
+                        }
+                        coverage |= 1 << sample;
+                        covered.get_or_insert(b);
+                    }
+                }
+
+                if let Some(b) = covered {
+                    let mut original = Vector3::<f32>::zeros();
+                    for (i, vertex) in order.iter().enumerate() {
+                        original[*vertex] = b[i];
+                    }
+                    fragment(x, y, original, coverage);
+                }
+            }
+        }
+    }
+
+    /// Draw triangles in order into a cleared target, returning the final depths and the
+    /// fragments of every triangle
+    fn draw(
+        width: u32,
+        height: u32,
+        triangles: &[[Point4<f32>; 3]],
+        sample_offsets: &[Vector2<f32>],
+        reciprocal_depth: bool,
+        hierarchical: bool,
+        reference: bool,
+    ) -> (Vec<f32>, Vec<Fragment>) {
+        let mut depth_buffer = vec![0.0; (width * height) as usize * sample_offsets.len()];
+        let mut hierarchical_depth =
+            vec![DepthRange::new(0.0); (block_count(width) * block_count(height)) as usize];
+        let mut target = DepthTarget {
+            depth_buffer: &mut depth_buffer,
+            hierarchical_depth: hierarchical.then_some(&mut hierarchical_depth[..]),
+            width,
+            rows: 0..height,
+            reciprocal_depth,
+            scratch: RasterScratch::default(),
+        };
+
+        let mut fragments = Vec::new();
+        for points in triangles {
+            let fragment = |x, y, b, coverage| fragments.push((x, y, b, coverage));
+            if reference {
+                rasterize_reference(
+                    &mut target,
+                    points,
+                    sample_offsets,
+                    DepthCompare::Greater,
+                    true,
+                    0.0,
+                    fragment,
+                );
+            } else {
+                rasterize(
+                    &mut target,
+                    points,
+                    sample_offsets,
+                    DepthCompare::Greater,
+                    true,
+                    0.0,
+                    fragment,
+                );
+            }
+        }
+        (depth_buffer, fragments)
+    }
+
+    /// Check that the SIMD rasterizer matches the reference exactly
+    fn assert_matches_reference(
+        width: u32,
+        height: u32,
+        triangles: &[[Point4<f32>; 3]],
+        sample_offsets: &[Vector2<f32>],
+    ) {
+        for reciprocal_depth in [false, true] {
+            let expected = draw(
+                width,
+                height,
+                triangles,
+                sample_offsets,
+                reciprocal_depth,
+                false,
+                true,
+            );
+            assert!(!expected.1.is_empty());
+            for hierarchical in [false, true] {
+                let actual = draw(
+                    width,
+                    height,
+                    triangles,
+                    sample_offsets,
+                    reciprocal_depth,
+                    hierarchical,
+                    false,
+                );
+                assert!(actual.0 == expected.0, "depths differ from the reference");
+                assert!(
+                    actual.1 == expected.1,
+                    "fragments differ from the reference"
+                );
+            }
+        }
+    }
+
+    /// Create a triangle of screen-space points at a constant depth
+    fn triangle(a: (f32, f32), b: (f32, f32), c: (f32, f32), depth: f32) -> [Point4<f32>; 3] {
+        [a, b, c].map(|(x, y)| Point4::<f32>::new(x, y, depth, 1.0))
+    }
+
+    /// Get the sample offsets of each anti-aliasing mode
+    fn sample_patterns() -> Vec<Vec<Vector2<f32>>> {
+        [
+            AntiAliasing::None,
+            AntiAliasing::Multisample(SampleCount::Two),
+            AntiAliasing::Multisample(SampleCount::Four),
+            AntiAliasing::Multisample(SampleCount::Eight),
+            AntiAliasing::Multisample(SampleCount::Sixteen),
+        ]
+        .iter()
+        .map(AntiAliasing::sample_offsets)
+        .collect()
+    }
+
+    #[test]
+    fn shared_edges_cover_each_sample_once() {
+        // Quads split along their diagonals, with edges passing exactly through sample points
+        let quads = [
+            [(1.5, 1.5), (9.5, 1.5), (9.5, 9.5), (1.5, 9.5)],
+            [(2.0, 0.5), (12.5, 3.0), (10.0, 11.5), (0.5, 8.0)],
+        ];
+
+        for offsets in sample_patterns() {
+            for [a, b, c, d] in quads {
+                let triangles = [triangle(a, b, c, 0.5), triangle(a, c, d, 0.5)];
+                assert_matches_reference(13, 12, &triangles, &offsets);
+
+                // Without a depth test, a sample covered twice would be reported twice
+                let mut depth_buffer = vec![0.0; 13 * 12 * offsets.len()];
+                let mut target = DepthTarget {
+                    depth_buffer: &mut depth_buffer,
+                    hierarchical_depth: None,
+                    width: 13,
+                    rows: 0..12,
+                    reciprocal_depth: false,
+                    scratch: RasterScratch::default(),
+                };
+                let mut counts = vec![0; 13 * 12 * offsets.len()];
+                for points in &triangles {
+                    rasterize(
+                        &mut target,
+                        points,
+                        &offsets,
+                        DepthCompare::Always,
+                        false,
+                        0.0,
+                        |x, y, _, coverage| {
+                            for sample in 0..offsets.len() {
+                                if coverage & (1 << sample) != 0 {
+                                    counts[((y * 13 + x) as usize) * offsets.len() + sample] += 1;
+                                }
+                            }
+                        },
+                    );
+                }
+                // Samples strictly inside the quad are covered by exactly one of its triangles
+                let mut interior = 0;
+                for (index, count) in counts.iter().enumerate() {
+                    let (pixel, sample) = (index / offsets.len(), index % offsets.len());
+                    let position =
+                        Vector2::<f32>::new((pixel % 13) as f32 + 0.5, (pixel / 13) as f32 + 0.5)
+                            + offsets[sample];
+                    let inside = [a, b, c, d]
+                        .iter()
+                        .zip([b, c, d, a].iter())
+                        .map(|(p, q)| {
+                            let edge = Vector2::<f32>::new(q.0 - p.0, q.1 - p.1);
+                            edge.perp(&(position - Vector2::<f32>::new(p.0, p.1)))
+                        })
+                        .all(|side| side > 0.0);
+
+                    assert!(*count <= 1);
+                    if inside {
+                        assert_eq!(*count, 1);
+                        interior += 1;
+                    }
+                }
+                assert!(interior > 0);
+            }
+        }
+    }
+
+    #[test]
+    fn thin_and_degenerate_triangles_match_reference() {
+        let triangles = [
+            // Slivers along each axis and a diagonal
+            triangle((0.3, 2.5), (12.7, 2.6), (0.3, 2.55), 0.4),
+            triangle((5.5, 0.2), (5.6, 11.8), (5.52, 0.2), 0.5),
+            triangle((0.1, 0.1), (12.9, 11.9), (12.9, 11.8), 0.6),
+            // Zero area, from repeated and collinear vertices
+            triangle((3.0, 3.0), (3.0, 3.0), (8.0, 9.0), 0.7),
+            triangle((1.0, 1.0), (5.0, 5.0), (9.0, 9.0), 0.7),
+            // Smaller than a pixel
+            triangle((6.4, 6.4), (6.6, 6.4), (6.5, 6.6), 0.8),
+        ];
+        for offsets in sample_patterns() {
+            assert_matches_reference(13, 12, &triangles, &offsets);
+        }
+
+        let degenerate = [
+            triangle((3.0, 3.0), (3.0, 3.0), (8.0, 9.0), 0.7),
+            triangle((1.0, 1.0), (5.0, 5.0), (9.0, 9.0), 0.7),
+        ];
+        let (_, fragments) = draw(
+            13,
+            12,
+            &degenerate,
+            &[Vector2::zeros()],
+            false,
+            false,
+            false,
+        );
+        assert!(fragments.is_empty());
+    }
+
+    #[test]
+    fn partial_lane_groups_match_reference() {
+        // Widths which leave one to three pixels in the last group of each row, with triangles
+        // starting away from a group boundary and reaching past the right edge
+        for width in [1, 5, 6, 7, 9, 13] {
+            let right = width as f32 + 2.0;
+            let triangles = [
+                triangle((1.3, 0.5), (right, 1.5), (2.7, 7.5), 0.5),
+                triangle((right, 0.0), (width as f32 - 0.5, 8.0), (-1.0, 3.0), 0.6),
+            ];
+            for offsets in sample_patterns() {
+                assert_matches_reference(width, 8, &triangles, &offsets);
+            }
+        }
+    }
+
+    #[test]
+    fn random_triangles_match_reference() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (width, height) = (37, 29);
+        let triangles: Vec<[Point4<f32>; 3]> = (0..100)
+            .map(|_| {
+                [(); 3].map(|_| {
+                    Point4::<f32>::new(
+                        rng.gen_range(-4.0..width as f32 + 4.0),
+                        rng.gen_range(-4.0..height as f32 + 4.0),
+                        rng.gen_range(0.1..1.0),
+                        1.0,
+                    )
+                })
+            })
+            .collect();
+
+        for offsets in sample_patterns() {
+            assert_matches_reference(width, height, &triangles, &offsets);
+        }
+    }
+}
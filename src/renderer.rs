@@ -1,257 +1,1369 @@
-use image::{ImageBuffer, Luma, Pixel, Rgba, RgbaImage};
-use nalgebra::{Point3, Vector2, Vector3};
-use std::cmp;
+use image::{GrayImage, ImageBuffer, Luma, Rgba, RgbaImage};
+use nalgebra::{Point3, Point4, Vector2, Vector3, Vector4};
+use rayon::prelude::*;
+use std::sync::Arc;
 
 use crate::{
-    camera::Camera, drawable::Drawable, mesh::triangle::Triangle, renderer_config::RendererConfig,
+    camera::Camera,
+    debug_draw::{Anchor, DebugDraw},
+    drawable::Drawable,
+    g_buffer::{GBufferSample, Hit, PrimitiveIds, NO_ID},
+    light::Light,
+    material::{BlendMode, Material},
+    mesh::triangle::Triangle,
+    point_cloud::PointShape,
+    post_process::{self, PostEffect},
+    rasterizer::{self, DepthRange, DepthTarget, RasterScratch},
+    renderer_config::{
+        CullMode, DepthBias, DepthCompare, LineStyle, RendererConfig, ShadingModel, Transparency,
+        Winding, Wireframe,
+    },
+    screen_space::{self, ScreenSurfaces},
+    shading::{self, Lighting, ShadingFunction},
+    shadow_map::ShadowMap,
+    text::Font,
+    tile::{ScreenTriangle, Tile, TILE_HEIGHT},
     utilities,
 };
 
-type DepthImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+pub type DepthImage = ImageBuffer<Luma<f32>, Vec<f32>>;
+
+/// An image of linear HDR colours, whose channels may exceed one, with alpha from zero to one
+pub type ColourImage = ImageBuffer<Rgba<f32>, Vec<f32>>;
 
 pub struct Renderer {
     width: u32,
     height: u32,
+    target_width: u32,
+    target_height: u32,
+    sample_offsets: Vec<Vector2<f32>>,
     config: RendererConfig,
-    colour_buffer: RgbaImage,
+    colour_buffer: ColourImage,
     depth_buffer: DepthImage,
+    hierarchical_depth: Vec<DepthRange>,
+    hdr_buffer: ColourImage,
+    output_buffer: RgbaImage,
+    output_depth_buffer: DepthImage,
     camera: Camera,
+    lights: Vec<Light>,
+    shadow_maps: Vec<Option<ShadowMap>>,
+    shadow_pass: Option<usize>,
+    transparent_triangles: Vec<(Triangle, Arc<Material>, DepthBias)>,
+    transparent_pass: bool,
+    depth_only: bool,
+    depth_bias: DepthBias,
+    line_style: LineStyle,
+    font: Font,
+    debug_draw: DebugDraw,
+    accumulation_buffer: Vec<Vector4<f32>>,
+    revealage_buffer: Vec<f32>,
+    g_buffer: Vec<GBufferSample>,
+    output_g_buffer: Vec<GBufferSample>,
+    id_buffer: Vec<Option<Hit>>,
+    materials: Vec<Arc<Material>>,
+    object_count: u32,
+    object_id: u32,
+    triangle_id: u32,
+    screen_triangles: Vec<ScreenTriangle>,
+    bins: Vec<Vec<usize>>,
+    unlit: Vec<Vec<usize>>,
 }
 
 impl Renderer {
-    ///
+    /// Create a renderer with an output of `width` by `height` pixels
     pub fn new(width: u32, height: u32, config: RendererConfig) -> Renderer {
-        let camera = Camera::new(
+        let mut camera = Camera::new(
             (width as f32) / (height as f32),
             config.field_of_view,
-            0.1,
-            10000.0,
+            config.z_near,
+            config.z_far,
+        );
+        camera.set_depth_encoding(config.get_depth_encoding());
+
+        let light = Light::directional(
+            Vector3::<f32>::new(-1.0, -0.5, -0.25),
+            Vector3::<f32>::new(1.0, 1.0, 1.0),
+            1.0,
         );
 
+        // Supersampling renders at a higher resolution, multisampling stores several samples
+        // side by side for each pixel
+        let (scale_x, scale_y) = config.anti_aliasing.supersample_factors();
+        let target_width = width * scale_x;
+        let target_height = height * scale_y;
+        let sample_offsets = config.anti_aliasing.sample_offsets();
+        let buffer_width = target_width * sample_offsets.len() as u32;
+        let clear_depth = config.clear_depth;
+        let (g_buffer_size, output_g_buffer_size) = if config.g_buffer || config.deferred_shading {
+            (buffer_width * target_height, width * height)
+        } else {
+            (0, 0)
+        };
+        let id_buffer_size = if config.id_buffer {
+            buffer_width * target_height
+        } else {
+            0
+        };
+
         Renderer {
-            width: width,
-            height: height,
-            config: config,
-            colour_buffer: RgbaImage::new(width, height),
-            depth_buffer: DepthImage::from_pixel(width, height, Luma([f32::NEG_INFINITY])),
-            camera: camera,
+            width,
+            height,
+            target_width,
+            target_height,
+            sample_offsets,
+            config,
+            colour_buffer: ColourImage::new(buffer_width, target_height),
+            depth_buffer: DepthImage::from_pixel(buffer_width, target_height, Luma([clear_depth])),
+            hierarchical_depth: vec![
+                DepthRange::new(clear_depth);
+                (rasterizer::block_count(target_width) * rasterizer::block_count(target_height))
+                    as usize
+            ],
+            hdr_buffer: ColourImage::new(width, height),
+            output_buffer: RgbaImage::new(width, height),
+            output_depth_buffer: DepthImage::from_pixel(width, height, Luma([clear_depth])),
+            camera,
+            lights: vec![light],
+            shadow_maps: Vec::new(),
+            shadow_pass: None,
+            transparent_triangles: Vec::new(),
+            transparent_pass: false,
+            depth_only: false,
+            depth_bias: DepthBias::default(),
+            line_style: LineStyle::default(),
+            font: Font::default(),
+            debug_draw: DebugDraw::default(),
+            accumulation_buffer: Vec::new(),
+            revealage_buffer: Vec::new(),
+            g_buffer: vec![GBufferSample::empty(); g_buffer_size as usize],
+            output_g_buffer: vec![GBufferSample::empty(); output_g_buffer_size as usize],
+            id_buffer: vec![None; id_buffer_size as usize],
+            materials: Vec::new(),
+            object_count: 0,
+            object_id: NO_ID,
+            triangle_id: 0,
+            screen_triangles: Vec::new(),
+            bins: vec![Vec::new(); target_height.div_ceil(TILE_HEIGHT) as usize],
+            unlit: vec![Vec::new(); target_height.div_ceil(TILE_HEIGHT) as usize],
         }
     }
 
-    ///
+    /// Get the width of the output in pixels
     #[inline]
     pub fn get_width(&self) -> u32 {
         self.width
     }
 
-    ///
+    /// Get the height of the output in pixels
     #[inline]
     pub fn get_height(&self) -> u32 {
         self.height
     }
 
-    ///
+    /// Get the config the renderer was created with
+    #[inline]
+    pub fn get_config(&self) -> &RendererConfig {
+        &self.config
+    }
+
+    /// Get the camera used for drawing
     #[inline]
     pub fn get_camera(&self) -> &Camera {
         &self.camera
     }
 
-    ///
+    /// Get the camera used for drawing, to move or turn it
     #[inline]
     pub fn get_camera_mut(&mut self) -> &mut Camera {
         &mut self.camera
     }
 
-    ///
+    /// Get the lights of the scene
+    #[inline]
+    pub fn get_lights(&self) -> &Vec<Light> {
+        &self.lights
+    }
+
+    /// Get the lights of the scene for editing
+    #[inline]
+    pub fn get_lights_mut(&mut self) -> &mut Vec<Light> {
+        &mut self.lights
+    }
+
+    /// Get the depth bias applied to triangles, lines and points
+    #[inline]
+    pub fn get_depth_bias(&self) -> DepthBias {
+        self.depth_bias
+    }
+
+    /// Set the depth bias applied to subsequently drawn triangles and lines
+    pub fn set_depth_bias(&mut self, depth_bias: DepthBias) {
+        self.depth_bias = depth_bias;
+    }
+
+    /// Get the style used to draw lines
+    #[inline]
+    pub fn get_line_style(&self) -> &LineStyle {
+        &self.line_style
+    }
+
+    /// Set the width, anti-aliasing and dash pattern of subsequently drawn lines
+    pub fn set_line_style(&mut self, line_style: LineStyle) {
+        self.line_style = line_style;
+    }
+
+    /// Get the font used to draw text
+    #[inline]
+    pub fn get_font(&self) -> &Font {
+        &self.font
+    }
+
+    /// Set the font of subsequently drawn text
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+    }
+
+    /// Get the debug-draw layer
+    #[inline]
+    pub fn get_debug_draw(&self) -> &DebugDraw {
+        &self.debug_draw
+    }
+
+    /// Get the layer of annotations which is drawn over the next finished frame
+    #[inline]
+    pub fn get_debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    /// Get the resolved colour of each pixel
     #[inline]
     pub fn get_colour_buffer_raw(&self) -> &Vec<u8> {
-        self.colour_buffer.as_raw()
+        self.output_buffer.as_raw()
     }
 
-    ///
+    /// Get the resolved colour of each pixel after post-processing and sRGB encoding, but before it
+    /// is quantized, as four floats per pixel
+    #[inline]
+    pub fn get_hdr_buffer_raw(&self) -> &Vec<f32> {
+        self.hdr_buffer.as_raw()
+    }
+
+    /// Get the G-buffer of each pixel, taken from its nearest sample. This is empty unless the
+    /// G-buffer is enabled in the config
+    #[inline]
+    pub fn get_g_buffer(&self) -> &Vec<GBufferSample> {
+        &self.output_g_buffer
+    }
+
+    /// Get the normal of each pixel in view-space, or zero where nothing was drawn
+    pub fn get_view_normal_buffer(&self) -> Vec<Vector3<f32>> {
+        let view_matrix = self.camera.get_view_matrix();
+        self.output_g_buffer
+            .iter()
+            .map(|sample| view_matrix.transform_vector(&sample.normal))
+            .collect()
+    }
+
+    /// Find the opaque triangle nearest the camera at a pixel of the output, from the nearest of
+    /// its samples. Objects are numbered in the order they were drawn this frame. This returns
+    /// `None` for the background, or unless the ID buffer is enabled in the config
+    pub fn pick(&self, x: u32, y: u32) -> Option<Hit> {
+        if self.id_buffer.is_empty() || x >= self.width || y >= self.height {
+            return None;
+        }
+
+        let (scale_x, scale_y) = self.config.anti_aliasing.supersample_factors();
+        let samples = self.sample_offsets.len() as u32;
+        let buffer_width = self.depth_buffer.width();
+        let mut nearest: Option<(f32, Hit)> = None;
+
+        for v in (y * scale_y)..((y + 1) * scale_y) {
+            for u in (x * scale_x * samples)..((x + 1) * scale_x * samples) {
+                let hit = match self.id_buffer[(v * buffer_width + u) as usize] {
+                    Some(hit) => hit,
+                    None => continue,
+                };
+
+                let depth = self.depth_buffer.get_pixel(u, v)[0];
+                if nearest.is_none_or(|(stored, _)| self.config.depth_compare.passes(depth, stored))
+                {
+                    nearest = Some((depth, hit));
+                }
+            }
+        }
+
+        nearest.map(|(_, hit)| hit)
+    }
+
+    /// Get the materials used this frame, indexed by the material IDs of the G-buffer
+    #[inline]
+    pub fn get_materials(&self) -> &Vec<Arc<Material>> {
+        &self.materials
+    }
+
+    /// Get the resolved depth of each pixel
     #[inline]
     pub fn get_depth_buffer_raw(&self) -> &Vec<f32> {
-        self.depth_buffer.as_raw()
+        self.output_depth_buffer.as_raw()
     }
 
-    ///
+    /// Get the resolved depth of each pixel as a view-space distance along the camera direction
+    pub fn get_linear_depth_buffer(&self) -> Vec<f32> {
+        self.output_depth_buffer
+            .iter()
+            .map(|depth| self.camera.linearize_depth(*depth))
+            .collect()
+    }
+
+    /// Save the output colour buffer as an image
     pub fn save(&self, filepath: &str) -> Result<(), &str> {
-        match self.colour_buffer.save(filepath) {
+        match self.output_buffer.save(filepath) {
+            Ok(_) => Ok(()),
+            Err(_) => Err("Failed to save image"),
+        }
+    }
+
+    /// Save the linear depth of each pixel as a greyscale image, from white at the nearest
+    /// surface to black at the farthest surface and beyond
+    pub fn save_depth(&self, filepath: &str) -> Result<(), &str> {
+        let depths = self.get_linear_depth_buffer();
+        let drawn = depths.iter().filter(|d| **d < self.config.z_far);
+        let nearest = drawn.clone().fold(f32::INFINITY, |a, b| a.min(*b));
+        let farthest = drawn.fold(f32::NEG_INFINITY, |a, b| a.max(*b));
+        let range = (farthest - nearest).max(f32::EPSILON);
+
+        let image = GrayImage::from_fn(self.width, self.height, |x, y| {
+            let depth = depths[(y * self.width + x) as usize];
+            let value = 1.0 - ((depth - nearest) / range).clamp(0.0, 1.0);
+            Luma([(value * 255.0).round() as u8])
+        });
+
+        match image.save(filepath) {
             Ok(_) => Ok(()),
             Err(_) => Err("Failed to save image"),
         }
     }
 
-    ///
+    /// Clear the buffers before drawing a new frame
     pub fn clear(&mut self) {
-        self.colour_buffer =
-            RgbaImage::from_pixel(self.width, self.height, self.config.clear_colour);
-        self.depth_buffer = DepthImage::from_pixel(self.width, self.height, Luma([-1.0]));
+        let (width, height) = self.colour_buffer.dimensions();
+        let clear_colour = utilities::colour_to_vector(self.config.clear_colour)
+            .push(self.config.clear_colour[3] as f32 / 255.0);
+        self.colour_buffer = ColourImage::from_pixel(width, height, Rgba(clear_colour.into()));
+        self.depth_buffer = DepthImage::from_pixel(width, height, Luma([self.config.clear_depth]));
+        self.hierarchical_depth
+            .fill(DepthRange::new(self.config.clear_depth));
+        self.shadow_maps.clear();
+        self.transparent_triangles.clear();
+        self.g_buffer.fill(GBufferSample::empty());
+        for unlit in &mut self.unlit {
+            unlit.clear();
+        }
+        self.id_buffer.fill(None);
+        self.materials.clear();
+        self.object_count = 0;
+        self.object_id = NO_ID;
+        self.triangle_id = 0;
     }
 
-    ///
+    /// Draw an object with the current config
     pub fn draw<T: Drawable>(&mut self, drawable: &T) {
-        if self.config.wireframe {
+        self.object_id = self.object_count;
+        self.object_count += 1;
+        self.triangle_id = 0;
+
+        let wireframe = self.config.wireframe;
+        if wireframe == Wireframe::AllEdges {
             drawable.draw_wireframe(self);
-        } else {
-            drawable.draw(self);
+            self.flush();
+            self.object_id = NO_ID;
+            return;
         }
+
+        // Push surfaces back from the edges drawn over them, using the slope of each triangle
+        let overlaid = wireframe != Wireframe::None || self.config.feature_edges.has_mesh_edges();
+        let depth_bias = self.depth_bias;
+        if overlaid {
+            self.depth_bias = DepthBias::new(
+                depth_bias.constant - self.config.wireframe_bias.constant,
+                depth_bias.slope - self.config.wireframe_bias.slope,
+            );
+        }
+
+        // Surfaces hide the edges behind them, either drawn or only written to depth
+        self.depth_only = wireframe == Wireframe::HiddenLine;
+        drawable.draw(self);
+        self.flush();
+        self.depth_only = false;
+        self.depth_bias = depth_bias;
+
+        if wireframe != Wireframe::None {
+            drawable.draw_wireframe(self);
+        }
+        if self.config.feature_edges.has_mesh_edges() {
+            drawable.draw_edges(self);
+        }
+        self.flush();
+        self.object_id = NO_ID;
     }
 
-    ///
+    /// Draw a line in the current line style, tested against and writing to the depth buffer
     pub fn line(&mut self, p0: Point3<f32>, p1: Point3<f32>, colour: Rgba<u8>) {
+        // Keep lines in front of previously submitted triangles, lit so that lines blend over
+        // their final colour
+        self.flush_and_light();
+
+        // Screen-space is only defined between the near and far planes
+        let (p0, p1) = match self.camera.clip_line(p0, p1) {
+            Some(segment) => segment,
+            None => return,
+        };
+
         // Convert to screen-space
         let p0 = self.to_screen(p0);
         let p1 = self.to_screen(p1);
+        let length = (p1.xy() - p0.xy()).norm();
 
-        let mut x0 = p0.x as i32;
-        let mut y0 = p0.y as i32;
-        let mut x1 = p1.x as i32;
-        let mut y1 = p1.y as i32;
+        // The line style is measured in output pixels, which may be supersampled unevenly
+        let (scale_x, scale_y) = self.config.anti_aliasing.supersample_factors();
+        let (scale_x, scale_y) = (scale_x as f32, scale_y as f32);
+        let delta = p1.xy() - p0.xy();
+        let output_length = Vector2::<f32>::new(delta.x / scale_x, delta.y / scale_y).norm();
+        let width = if length > 0.0 {
+            self.line_style.width * scale_x * scale_y * output_length / length
+        } else {
+            self.line_style.width * scale_x.max(scale_y)
+        };
+
+        // Depth is interpolated in screen-space, then offset by the depth bias
+        let reciprocal_depth = self.camera.has_reciprocal_depth();
+        let stored_depth = |z: f32| if reciprocal_depth { 1.0 / z } else { z };
+        let (d0, d1) = (stored_depth(p0.z), stored_depth(p1.z));
+        let bias = self.camera.get_depth_encoding().nearer()
+            * self
+                .depth_bias
+                .offset(d0.abs().max(d1.abs()), (d1 - d0) / length.max(1.0));
 
-        let steep = (x0 - x1).abs() < (y0 - y1).abs();
+        let line_style = self.line_style.clone();
+        rasterizer::rasterize_line(
+            p0.xy().coords,
+            p1.xy().coords,
+            width,
+            line_style.anti_aliased,
+            self.target_width,
+            self.target_height,
+            |u, v, t, coverage| {
+                if !line_style.is_drawn(t * output_length) {
+                    return;
+                }
 
-        if steep {
-            // Swap x and y
-            std::mem::swap(&mut x0, &mut y0);
-            std::mem::swap(&mut x1, &mut y1);
+                let depth = stored_depth(p0.z + (p1.z - p0.z) * t) + bias;
+                self.plot(u, v, depth, colour, coverage);
+            },
+        );
+    }
+
+    /// Draw a point as a square or disc `size` output pixels across, tested against the depth
+    /// buffer
+    pub fn point(&mut self, p: Point3<f32>, colour: Rgba<u8>, size: f32, shape: PointShape) {
+        // Points neither cast shadows nor hide edges
+        if self.shadow_pass.is_some() || self.depth_only {
+            return;
         }
 
-        if x0 > x1 {
-            // Swap ends
-            std::mem::swap(&mut x0, &mut x1);
-            std::mem::swap(&mut y0, &mut y1);
+        // Keep points in front of previously submitted triangles, lit so that the lighting pass
+        // doesn't overwrite points
+        self.flush_and_light();
+
+        if !self.camera.is_within_depth_range(&p) {
+            return;
         }
 
-        let dx: i32 = x1 - x0;
-        let dy: i32 = y1 - y0;
-        let derr: i32 = 2 * dy.abs();
+        let p = self.to_screen(p);
+        let depth = if self.camera.has_reciprocal_depth() {
+            1.0 / p.z
+        } else {
+            p.z
+        };
+        let depth =
+            depth + self.camera.get_depth_encoding().nearer() * self.depth_bias.offset(depth, 0.0);
 
-        let mut err: i32 = 0;
-        let mut y: i32 = y0;
+        // Cover the pixels whose centres are inside the shape, sized in output pixels
+        let (scale_x, scale_y) = self.config.anti_aliasing.supersample_factors();
+        let radius = size.max(1.0) / 2.0;
+        let radius = Vector2::<f32>::new(radius * scale_x as f32, radius * scale_y as f32);
+        let first_x = (p.x - radius.x).round().max(0.0) as u32;
+        let last_x = (p.x + radius.x).round().min(self.target_width as f32) as u32;
+        let first_y = (p.y - radius.y).round().max(0.0) as u32;
+        let last_y = (p.y + radius.y).round().min(self.target_height as f32) as u32;
 
-        for x in x0..=x1 {
-            if x < 0 || x as u32 >= self.width || y < 0 || y as u32 >= self.height {
+        for v in first_y..last_y {
+            for u in first_x..last_x {
+                let offset = Vector2::<f32>::new(u as f32 + 0.5 - p.x, v as f32 + 0.5 - p.y);
+                if shape == PointShape::Disc && offset.component_div(&radius).norm_squared() > 1.0 {
+                    continue;
+                }
+                self.plot(u, v, depth, colour, 1.0);
+            }
+        }
+    }
+
+    /// Test a pixel of a line or point against the depth buffer, and blend its colour over each
+    /// sample which passes
+    fn plot(&mut self, u: u32, v: u32, depth: f32, colour: Rgba<u8>, coverage: f32) {
+        let samples = self.sample_offsets.len() as u32;
+
+        // Lines and points cover every sample of a pixel
+        for sample in 0..samples {
+            let column = u * samples + sample;
+            let stored = self.depth_buffer.get_pixel(column, v)[0];
+            if !self.config.depth_compare.passes(depth, stored) {
                 continue;
             }
 
-            if steep {
-                self.colour_buffer.put_pixel(y as u32, x as u32, colour);
-            } else {
-                self.colour_buffer.put_pixel(x as u32, y as u32, colour);
+            // Only fully covered pixels write depth, so anti-aliased edges don't hide neighbouring
+            // lines
+            if self.config.depth_write && coverage >= 1.0 {
+                self.depth_buffer.put_pixel(column, v, Luma([depth]));
+
+                // Widen the depth range of the block to keep it conservative
+                let blocks_x = rasterizer::block_count(self.target_width);
+                let block = (v / rasterizer::BLOCK_SIZE) * blocks_x + u / rasterizer::BLOCK_SIZE;
+                let range = &mut self.hierarchical_depth[block as usize];
+                range.min = range.min.min(depth);
+                range.max = range.max.max(depth);
             }
 
-            err += derr;
-            if err > dx {
-                y += if y1 > y0 { 1 } else { -1 };
-                err -= 2 * dx;
+            let pixel = self.colour_buffer.get_pixel_mut(column, v);
+            let colour = utilities::colour_to_vector(colour).push(colour[3] as f32 / 255.0);
+            if coverage >= 1.0 {
+                *pixel = Rgba(colour.into());
+            } else {
+                for channel in 0..3 {
+                    pixel[channel] = pixel[channel] * (1.0 - coverage) + colour[channel] * coverage;
+                }
             }
         }
     }
 
-    ///
-    pub fn triangle(&mut self, tri: &Triangle) {
+    /// Draw a triangle with `material`, or add it to a shadow map during a shadow pass
+    pub fn triangle(&mut self, tri: &Triangle, material: &Arc<Material>) {
+        // Render only depth while filling a shadow map, where blended triangles let light through
+        if let Some(index) = self.shadow_pass {
+            if let Some(shadow_map) = &mut self.shadow_maps[index] {
+                if material.blend_mode == BlendMode::Opaque {
+                    shadow_map.triangle(tri);
+                }
+            }
+            return;
+        }
+
+        // Number triangles in the order they are submitted, whether or not they are drawn
+        let triangle_id = self.triangle_id;
+        if !self.transparent_pass {
+            self.triangle_id += 1;
+        }
+
+        // Defer blended triangles until all opaque geometry has been drawn
+        let blend_mode = material.blend_mode;
+        if blend_mode != BlendMode::Opaque && !self.transparent_pass {
+            // Blended triangles don't hide anything in a depth-only pass
+            if !self.depth_only {
+                self.transparent_triangles.push((
+                    tri.clone(),
+                    Arc::clone(material),
+                    self.depth_bias,
+                ));
+            }
+            return;
+        }
+
         // Get triangle normal
-        let normal = (tri.c.position - tri.a.position)
-            .cross(&(tri.b.position - tri.a.position))
+        let normal = (tri.b.position - tri.a.position)
+            .cross(&(tri.c.position - tri.a.position))
             .normalize();
 
-        // Calculate triangle visibility
-        let visibility0 = (tri.a.position - self.camera.get_position()).dot(&normal);
-        let visibility1 = (tri.b.position - self.camera.get_position()).dot(&normal);
-        let visibility2 = (tri.c.position - self.camera.get_position()).dot(&normal);
-        if (visibility0 < 0.0) && (visibility1 < 0.0) && (visibility2 < 0.0) {
-            return;
+        // Screen-space is only defined between the near and far planes
+        let vertices = [&tri.a, &tri.b, &tri.c];
+        if vertices
+            .iter()
+            .all(|v| self.camera.is_within_depth_range(&v.position))
+        {
+            self.submit_triangle(tri, material, normal, triangle_id);
+        } else {
+            for part in self.camera.clip_triangle(tri) {
+                self.submit_triangle(&part, material, normal, triangle_id);
+            }
         }
+    }
+
+    /// Cull a triangle between the near and far planes, then add it to the bin of each tile it
+    /// overlaps
+    fn submit_triangle(
+        &mut self,
+        tri: &Triangle,
+        material: &Arc<Material>,
+        normal: Vector3<f32>,
+        triangle_id: u32,
+    ) {
+        let blend_mode = material.blend_mode;
 
         // Convert to screen-space
-        let p0 = self.to_screen(tri.a.position);
-        let p1 = self.to_screen(tri.b.position);
-        let p2 = self.to_screen(tri.c.position);
+        let points = [
+            self.to_screen(tri.a.position),
+            self.to_screen(tri.b.position),
+            self.to_screen(tri.c.position),
+        ];
 
-        // Find the screen-space bounding box of this triangle
-        let mut bb_min = Vector2::<i32>::new((self.width - 1) as i32, (self.height - 1) as i32);
-        let mut bb_max = Vector2::<i32>::new(0, 0);
-        let clamp = Vector2::<i32>::new(bb_min.x, bb_min.y);
+        // Screen-space y points down, so a triangle counter-clockwise on screen has a negative
+        // area
+        let area = (points[1].xy() - points[0].xy()).perp(&(points[2].xy() - points[0].xy()));
+        let front_facing = match self.config.front_face {
+            Winding::Clockwise => area > 0.0,
+            Winding::CounterClockwise => area < 0.0,
+        };
+        let culled = match self.config.cull_mode {
+            CullMode::None => false,
+            CullMode::Front => front_facing,
+            CullMode::Back => !front_facing,
+        };
+        if culled {
+            return;
+        }
+
+        let weighted_blended = self.transparent_pass
+            && blend_mode == BlendMode::Alpha
+            && self.config.transparency == Transparency::WeightedBlended;
+        let ids = PrimitiveIds {
+            object: self.object_id,
+            triangle: triangle_id,
+            material: self.material_id(material),
+        };
+        let triangle = ScreenTriangle::new(
+            tri,
+            material,
+            points,
+            normal,
+            weighted_blended,
+            self.triangle_depth_bias(&points),
+            ids,
+        );
 
-        for p in &[p0, p1, p2] {
-            bb_min.x = cmp::max(0, cmp::min(bb_min.x, p.x.floor() as i32));
-            bb_min.y = cmp::max(0, cmp::min(bb_min.y, p.y.floor() as i32));
-            bb_max.x = cmp::min(clamp.x, cmp::max(bb_max.x, p.x.ceil() as i32));
-            bb_max.y = cmp::min(clamp.y, cmp::max(bb_max.y, p.y.ceil() as i32));
+        // Add the triangle to the bin of each tile it overlaps
+        let rows = match triangle.rows(self.target_height) {
+            Some(rows) if !rows.is_empty() => rows,
+            _ => return,
+        };
+        let index = self.screen_triangles.len();
+        let first_tile = (rows.start / TILE_HEIGHT) as usize;
+        let last_tile = ((rows.end - 1) / TILE_HEIGHT) as usize;
+        for bin in &mut self.bins[first_tile..=last_tile] {
+            bin.push(index);
         }
+        self.screen_triangles.push(triangle);
+    }
+
+    /// Rasterize the binned triangles, drawing the tiles of the screen in parallel
+    fn flush(&mut self) {
+        self.flush_tiles(false);
+    }
+
+    /// Rasterize the binned triangles, then light the G-buffer samples written since the last
+    /// lighting pass when shading is deferred
+    fn flush_and_light(&mut self) {
+        self.flush_tiles(true);
+    }
 
-        if (bb_max.x == bb_min.x) || (bb_max.y == bb_min.y) {
+    /// Rasterize the binned triangles and optionally run the deferred lighting pass, drawing the
+    /// tiles of the screen in parallel
+    fn flush_tiles(&mut self, light: bool) {
+        let deferred = self.config.deferred_shading;
+        let light = light && deferred && self.unlit.iter().any(|unlit| !unlit.is_empty());
+        if self.screen_triangles.is_empty() && !light {
             return;
         }
 
-        // Calculate facet lighting
-        let light_direction = Vector3::<f32>::new(-1.0, -0.5, -0.25).normalize();
-        let lighting_intensity = normal.dot(&light_direction);
-        let colour = tri.colour.map_with_alpha(
-            |c| utilities::clamp_f32((c as f32) * lighting_intensity, 0.0, 255.0) as u8,
-            |a| a,
+        // Split each sample buffer into the rows owned by each tile
+        let buffer_width = self.colour_buffer.width();
+        let tile_size = (buffer_width * TILE_HEIGHT) as usize;
+        let mut colour_tiles = self.colour_buffer.chunks_mut(tile_size * 4);
+        let mut depth_tiles = self.depth_buffer.chunks_mut(tile_size);
+        let mut hierarchical_tiles = self.hierarchical_depth.chunks_mut(
+            (rasterizer::block_count(self.target_width) * TILE_HEIGHT / rasterizer::BLOCK_SIZE)
+                as usize,
         );
+        let mut accumulation_tiles = self.accumulation_buffer.chunks_mut(tile_size);
+        let mut revealage_tiles = self.revealage_buffer.chunks_mut(tile_size);
+        let mut g_buffer_tiles = self.g_buffer.chunks_mut(tile_size);
+        let mut id_tiles = self.id_buffer.chunks_mut(tile_size);
+        let mut unlit_tiles = self.unlit.iter_mut();
 
-        // Calculate barycentric coordinate frame
-        let b0 = p1 - p0;
-        let b1 = p2 - p0;
-
-        let b00 = b0.dot(&b0);
-        let b01 = b0.dot(&b1);
-        let b11 = b1.dot(&b1);
-        let denominator = (b00 * b11) - (b01 * b01);
-
-        // Render
-        for y in bb_min.y..=bb_max.y {
-            for x in bb_min.x..=bb_max.x {
-                // Screen-space coordinates of this pixel
-                let p = Point3::<f32>::new(x as f32, y as f32, 0.0);
-
-                // Find the barycentric coordinates of this pixel
-                let b = {
-                    let b2 = p - p0;
-                    let b20 = b2.dot(&b0);
-                    let b21 = b2.dot(&b1);
-                    let by = ((b11 * b20) - (b01 * b21)) / denominator;
-                    let bz = ((b00 * b21) - (b01 * b20)) / denominator;
-                    let bx = 1.0 - by - bz;
-                    Vector3::<f32>::new(bx, by, bz)
-                };
+        let tiles: Vec<Tile> = (0..self.bins.len() as u32)
+            .map(|index| Tile {
+                depth: DepthTarget {
+                    depth_buffer: depth_tiles.next().unwrap(),
+                    hierarchical_depth: hierarchical_tiles.next(),
+                    width: self.target_width,
+                    rows: (index * TILE_HEIGHT)
+                        ..((index + 1) * TILE_HEIGHT).min(self.target_height),
+                    reciprocal_depth: self.camera.has_reciprocal_depth(),
+                    scratch: RasterScratch::default(),
+                },
+                colour_buffer: colour_tiles.next().unwrap(),
+                accumulation_buffer: accumulation_tiles.next().unwrap_or_default(),
+                revealage_buffer: revealage_tiles.next().unwrap_or_default(),
+                g_buffer: g_buffer_tiles.next().unwrap_or_default(),
+                id_buffer: id_tiles.next().unwrap_or_default(),
+                deferred,
+                unlit: unlit_tiles.next().unwrap(),
+            })
+            .collect();
 
-                // If this pixel is outside of the triangle, ignore it
-                if (b.x < 0.0)
-                    || (b.x > 1.0)
-                    || (b.y < 0.0)
-                    || (b.y > 1.0)
-                    || (b.z < 0.0)
-                    || (b.z > 1.0)
-                {
+        let shade: ShadingFunction = match self.config.shading_model {
+            ShadingModel::BlinnPhong => shading::blinn_phong,
+            ShadingModel::PhysicallyBased => shading::pbr,
+        };
+
+        let lighting = Lighting {
+            lights: &self.lights,
+            shadow_maps: &self.shadow_maps,
+            ambient_light: &self.config.ambient_light,
+            eye: *self.camera.get_position(),
+            texture_filter: self.config.texture_filter,
+        };
+        let triangles = &self.screen_triangles;
+        let materials = &self.materials;
+        let sample_offsets = &self.sample_offsets;
+        let depth_compare = self.config.depth_compare;
+        let depth_write = self.config.depth_write;
+        let depth_pre_pass = self.config.depth_pre_pass && depth_write;
+        let depth_only = self.depth_only;
+
+        // Triangles are drawn in submission order within each tile
+        tiles
+            .into_par_iter()
+            .zip(self.bins.par_iter())
+            .for_each(|(mut tile, bin)| {
+                let triangles = bin.iter().map(|index| &triangles[*index]);
+
+                if depth_pre_pass || depth_only {
+                    for triangle in triangles.clone().filter(|t| t.is_opaque()) {
+                        tile.depth(triangle, sample_offsets, depth_compare);
+                    }
+                }
+                if depth_only {
+                    return;
+                }
+
+                for triangle in triangles {
+                    // After a depth pre-pass, opaque triangles are only shaded where they are the
+                    // visible surface. Blended triangles never write depth
+                    let (depth_compare, depth_write) = if !triangle.is_opaque() {
+                        (depth_compare, false)
+                    } else if depth_pre_pass {
+                        (DepthCompare::Equal, false)
+                    } else {
+                        (depth_compare, depth_write)
+                    };
+
+                    tile.triangle(
+                        triangle,
+                        sample_offsets,
+                        shade,
+                        &lighting,
+                        depth_compare,
+                        depth_write,
+                    );
+                }
+
+                if light {
+                    tile.light(materials, shade, &lighting);
+                }
+            });
+
+        self.screen_triangles.clear();
+        for bin in &mut self.bins {
+            bin.clear();
+        }
+    }
+
+    /// Complete the frame by drawing the deferred transparent geometry and resolving the samples
+    /// of each pixel into the output buffers
+    pub fn finish(&mut self) {
+        let mut triangles = std::mem::take(&mut self.transparent_triangles);
+        let weighted_blended = self.config.transparency == Transparency::WeightedBlended;
+
+        if weighted_blended {
+            let size = self.colour_buffer.len() / 4;
+            self.accumulation_buffer = vec![Vector4::<f32>::zeros(); size];
+            self.revealage_buffer = vec![1.0; size];
+        } else {
+            // Draw from back to front
+            let eye = *self.camera.get_position();
+            let distance = |tri: &Triangle| {
+                let centroid =
+                    (tri.a.position.coords + tri.b.position.coords + tri.c.position.coords) / 3.0;
+                (Point3::<f32>::from(centroid) - eye).norm_squared()
+            };
+            triangles.sort_by(|(a, _, _), (b, _, _)| distance(b).total_cmp(&distance(a)));
+        }
+
+        // Light the opaque geometry once, before anything is blended over it
+        self.flush_and_light();
+        if self.config.ssao {
+            self.apply_ambient_occlusion();
+        }
+
+        self.transparent_pass = true;
+        let depth_bias = self.depth_bias;
+        for (triangle, material, triangle_depth_bias) in &triangles {
+            self.depth_bias = *triangle_depth_bias;
+            self.triangle(triangle, material);
+        }
+        self.flush();
+        self.depth_bias = depth_bias;
+        self.transparent_pass = false;
+
+        if weighted_blended {
+            // Composite the accumulated transparent fragments over the opaque image
+            for (index, pixel) in self.colour_buffer.pixels_mut().enumerate() {
+                let revealage = self.revealage_buffer[index];
+                if revealage >= 1.0 {
                     continue;
                 }
 
-                // Calculate the depth
-                let depth = 1.0 / ((b.x / p0.z) + (b.y / p1.z) + (b.z / p2.z));
+                let accumulation = self.accumulation_buffer[index];
+                let average = accumulation.xyz() / accumulation.w.max(1e-5);
+                let destination = Vector3::<f32>::new(pixel[0], pixel[1], pixel[2]);
+                let colour = average.lerp(&destination, revealage);
+                pixel.0[..3].copy_from_slice(colour.as_slice());
+            }
+        }
+
+        let anchors = self.draw_debug();
+        self.resolve();
+        self.post_process();
+
+        if self.config.feature_edges.screen_space {
+            self.draw_screen_space_edges();
+        }
+
+        // Labels are drawn at the output resolution, so they stay sharp
+        for anchor in anchors {
+            if self.debug_draw.get_depth_test() && self.is_occluded(&anchor.position) {
+                continue;
+            }
+            self.text_at(&anchor.text, anchor.position, anchor.colour);
+        }
+    }
+
+    /// Draw text in the current font with its top-left corner at a pixel of the output. Text is
+    /// drawn over the finished frame, so this is called after `finish`
+    pub fn text(&mut self, text: &str, x: f32, y: f32, colour: Rgba<u8>) {
+        let (left, top) = (x.round() as i32, y.round() as i32);
+        let (width, height) = (self.width as i32, self.height as i32);
+        let output_buffer = &mut self.output_buffer;
+
+        self.font.rasterize(text, |u, v, coverage| {
+            let (u, v) = (left + u, top + v);
+            if u < 0 || v < 0 || u >= width || v >= height {
+                return;
+            }
+
+            let coverage = coverage * colour[3] as f32 / 255.0;
+            let pixel = output_buffer.get_pixel_mut(u as u32, v as u32);
+            for channel in 0..3 {
+                pixel[channel] = (pixel[channel] as f32 * (1.0 - coverage)
+                    + colour[channel] as f32 * coverage)
+                    .round() as u8;
+            }
+        });
+    }
+
+    /// Draw text in the current font beside the projection of a world-space point, which is
+    /// skipped when the point is outside the view. Called after `finish`, like `text`
+    pub fn text_at(&mut self, text: &str, position: Point3<f32>, colour: Rgba<u8>) {
+        if !self.camera.is_within_depth_range(&position) {
+            return;
+        }
+
+        // Centre the first line vertically to the right of the point
+        let p = self.camera.to_screen(position, self.width, self.height);
+        let line_height = self.font.get_line_height();
+        self.text(text, p.x + 6.0, p.y - line_height / 2.0, colour);
+    }
+
+    /// Draw and then discard the annotations collected during this frame, returning the anchors
+    /// whose labels are drawn once the frame is resolved
+    fn draw_debug(&mut self) -> Vec<Anchor> {
+        let (lines, anchors) = self.debug_draw.take();
+        let (depth_compare, depth_write) = (self.config.depth_compare, self.config.depth_write);
+        if !self.debug_draw.get_depth_test() {
+            self.config.depth_compare = DepthCompare::Always;
+            self.config.depth_write = false;
+        }
+
+        for line in lines {
+            self.line(line.p0, line.p1, line.colour);
+        }
+        for anchor in &anchors {
+            self.point(anchor.position, anchor.colour, 5.0, PointShape::Square);
+        }
+
+        self.config.depth_compare = depth_compare;
+        self.config.depth_write = depth_write;
+        anchors
+    }
+
+    /// Check whether a point is hidden behind the resolved depth of the pixel it projects onto
+    fn is_occluded(&self, p: &Point3<f32>) -> bool {
+        if !self.camera.is_within_depth_range(p) {
+            return true;
+        }
+
+        let screen = self.camera.to_screen(*p, self.width, self.height);
+        if screen.x < 0.0
+            || screen.y < 0.0
+            || screen.x >= self.width as f32
+            || screen.y >= self.height as f32
+        {
+            return false;
+        }
+
+        let depth = if self.camera.has_reciprocal_depth() {
+            1.0 / screen.z
+        } else {
+            screen.z
+        };
+        let stored = self
+            .output_depth_buffer
+            .get_pixel(screen.x as u32, screen.y as u32)[0];
+
+        // The anchor's own marker wrote a depth slightly nearer than the point
+        let tolerance = self.depth_bias.offset(depth, 0.0).max(depth.abs() * 1e-4);
+        let nearer = self.camera.get_depth_encoding().nearer();
+        (stored - depth) * nearer > tolerance
+    }
+
+    /// Average the samples of each pixel into the output colour buffer, keeping the nearest depth
+    fn resolve(&mut self) {
+        let (scale_x, scale_y) = self.config.anti_aliasing.supersample_factors();
+        let samples = self.sample_offsets.len() as u32;
+
+        if scale_x * scale_y * samples == 1 {
+            self.hdr_buffer.clone_from(&self.colour_buffer);
+            self.output_depth_buffer.clone_from(&self.depth_buffer);
+            self.output_g_buffer.clone_from(&self.g_buffer);
+            return;
+        }
+
+        let buffer_width = self.colour_buffer.width();
+
+        let count = (scale_x * scale_y * samples) as f32;
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut colour = Vector4::<f32>::zeros();
+                let mut depth = self.config.clear_depth;
+                let mut nearest = None;
+
+                for v in (y * scale_y)..((y + 1) * scale_y) {
+                    for u in (x * scale_x * samples)..((x + 1) * scale_x * samples) {
+                        colour += Vector4::<f32>::from(self.colour_buffer.get_pixel(u, v).0);
+                        let sample = self.depth_buffer.get_pixel(u, v)[0];
+                        if self.config.depth_compare.passes(sample, depth) {
+                            depth = sample;
+                            nearest = Some((v * buffer_width + u) as usize);
+                        }
+                    }
+                }
+
+                let colour = colour / count;
+                self.hdr_buffer
+                    .put_pixel(x, y, Rgba([colour.x, colour.y, colour.z, colour.w]));
+                self.output_depth_buffer.put_pixel(x, y, Luma([depth]));
+
+                if !self.g_buffer.is_empty() {
+                    self.output_g_buffer[(y * self.width + x) as usize] =
+                        nearest.map_or_else(GBufferSample::empty, |index| self.g_buffer[index]);
+                }
+            }
+        }
+    }
+
+    /// Apply the post-processing chain to the resolved colours, then encode them to sRGB unless the
+    /// chain already has, and quantize them into the output buffer
+    fn post_process(&mut self) {
+        let effects = &self.config.post_process;
+        post_process::apply(effects, &mut self.hdr_buffer);
+        if !effects.iter().any(|e| matches!(e, PostEffect::SrgbEncode)) {
+            post_process::apply(&[PostEffect::SrgbEncode], &mut self.hdr_buffer);
+        }
+
+        for (output, pixel) in self
+            .output_buffer
+            .pixels_mut()
+            .zip(self.hdr_buffer.pixels())
+        {
+            let colour = Vector3::<f32>::new(pixel[0], pixel[1], pixel[2]);
+            let alpha = (utilities::clamp_f32(pixel[3], 0.0, 1.0) * 255.0).round() as u8;
+            *output = utilities::vector_to_colour(colour, alpha);
+        }
+    }
+
+    /// Darken the opaque surfaces drawn so far by their screen-space ambient occlusion, using the
+    /// normals of the G-buffer when it is enabled or normals reconstructed from depth otherwise
+    fn apply_ambient_occlusion(&mut self) {
+        let (width, height) = (self.target_width, self.target_height);
+        let samples = self.sample_offsets.len() as u32;
+        let buffer_width = self.depth_buffer.width();
+
+        // Take the nearest sample of each pixel
+        let nearest: Vec<Option<(f32, usize)>> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                let mut nearest = None;
+                let mut depth = self.config.clear_depth;
+                for u in (x * samples)..((x + 1) * samples) {
+                    let sample = self.depth_buffer.get_pixel(u, y)[0];
+                    if self.config.depth_compare.passes(sample, depth) {
+                        depth = sample;
+                        nearest = Some((sample, (y * buffer_width + u) as usize));
+                    }
+                }
+                nearest
+            })
+            .collect();
+        let depth_image = DepthImage::from_fn(width, height, |x, y| {
+            let nearest = nearest[(y * width + x) as usize];
+            Luma([nearest.map_or(self.config.clear_depth, |(depth, _)| depth)])
+        });
+
+        let mut surfaces =
+            ScreenSurfaces::reconstruct(&self.camera, &depth_image, self.config.clear_depth);
+        if !self.g_buffer.is_empty() {
+            surfaces.normals = nearest
+                .iter()
+                .map(|nearest| {
+                    let (_, index) = (*nearest)?;
+                    let sample = &self.g_buffer[index];
+                    (!sample.is_empty()).then_some(sample.normal)
+                })
+                .collect();
+        }
+
+        let occlusion = screen_space::ambient_occlusion(
+            &surfaces,
+            &self.camera,
+            self.config.ssao_radius,
+            self.config.ssao_samples,
+            self.config.ssao_intensity,
+        );
+        let occlusion = screen_space::blur(&occlusion, &surfaces, self.config.ssao_blur_radius);
+
+        for (i, factor) in occlusion.iter().enumerate() {
+            if *factor >= 1.0 {
+                continue;
+            }
+
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            for u in (x * samples)..((x + 1) * samples) {
+                let pixel = self.colour_buffer.get_pixel_mut(u, y);
+                for channel in 0..3 {
+                    pixel[channel] *= factor;
+                }
+            }
+        }
+    }
+
+    /// Draw edges over the output where its depth jumps, or where normals reconstructed from depth
+    /// bend by more than the crease angle
+    fn draw_screen_space_edges(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let feature_edges = self.config.feature_edges;
+
+        let surfaces = ScreenSurfaces::reconstruct(
+            &self.camera,
+            &self.output_depth_buffer,
+            self.config.clear_depth,
+        );
+        let (positions, depths, normals) =
+            (&surfaces.positions, &surfaces.depths, &surfaces.normals);
+        let index = |x: i64, y: i64| surfaces.index(x, y);
+
+        let crease_cos = feature_edges.crease_angle.cos();
+        let is_edge = |x: i64, y: i64| -> bool {
+            let i = index(x, y).unwrap();
+            if positions[i].is_none() {
+                return false;
+            }
+
+            for (dx, dy) in [(1, 0), (0, 1)] {
+                let (before, after) = (index(x - dx, y - dy), index(x + dx, y + dy));
 
-                // Set pixel
-                let (u, v) = (x as u32, y as u32);
+                // Outline drawn objects against the background
+                if [before, after]
+                    .into_iter()
+                    .flatten()
+                    .any(|j| positions[j].is_none())
+                {
+                    return true;
+                }
 
-                if depth > self.depth_buffer.get_pixel(u, v)[0] {
-                    self.colour_buffer.put_pixel(u, v, colour);
-                    self.depth_buffer.put_pixel(u, v, Luma([depth]));
+                // Reciprocal depth is affine across a plane, so a large second difference is a
+                // jump in depth. Only the nearer side of the jump is marked
+                if let (Some(before), Some(after)) = (before, after) {
+                    let curvature = 2.0 / depths[i] - 1.0 / depths[before] - 1.0 / depths[after];
+                    if curvature > feature_edges.depth_threshold / depths[i] {
+                        return true;
+                    }
                 }
+
+                if let Some(normal) = normals[i] {
+                    if [before, after]
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|j| normals[j])
+                        .any(|neighbour| normal.dot(&neighbour) < crease_cos)
+                    {
+                        return true;
+                    }
+                }
+            }
+            false
+        };
+
+        let edges: Vec<(u32, u32)> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|(x, y)| is_edge(*x as i64, *y as i64))
+            .collect();
+        for (x, y) in edges {
+            self.output_buffer.put_pixel(x, y, feature_edges.colour);
+        }
+    }
+
+    /// Render the depth of a drawable into the shadow map of each shadow-casting light
+    pub fn cast_shadows<T: Drawable>(&mut self, drawable: &T) {
+        if !self.config.shadows {
+            return;
+        }
+
+        // Create the shadow maps on the first shadow pass of a frame
+        if self.shadow_maps.is_empty() {
+            self.shadow_maps = self
+                .lights
+                .iter()
+                .map(|light| ShadowMap::new(light, &self.config))
+                .collect();
+        }
+
+        for index in 0..self.shadow_maps.len() {
+            self.shadow_pass = Some(index);
+            drawable.draw(self);
+        }
+        self.shadow_pass = None;
+    }
+
+    /// Get the offset to add to the stored depth of a triangle, from the current depth bias
+    fn triangle_depth_bias(&self, points: &[Point4<f32>; 3]) -> f32 {
+        if self.depth_bias == DepthBias::default() {
+            return 0.0;
+        }
+
+        let depths = points.map(|p| {
+            if self.camera.has_reciprocal_depth() {
+                1.0 / p.z
+            } else {
+                p.z
+            }
+        });
+
+        // Screen-space gradient of the depth over the plane of the triangle
+        let e1 = points[1].xy() - points[0].xy();
+        let e2 = points[2].xy() - points[0].xy();
+        let area = e1.perp(&e2);
+        let (dz1, dz2) = (depths[1] - depths[0], depths[2] - depths[0]);
+        let dz_dx = (dz1 * e2.y - dz2 * e1.y) / area;
+        let dz_dy = (dz2 * e1.x - dz1 * e2.x) / area;
+
+        let depth = depths.iter().fold(0.0, |a: f32, b| a.max(b.abs()));
+        let slope = dz_dx.abs().max(dz_dy.abs());
+        self.camera.get_depth_encoding().nearer() * self.depth_bias.offset(depth, slope)
+    }
+
+    /// Get the index of a material in this frame's material table, adding it if it is new
+    fn material_id(&mut self, material: &Arc<Material>) -> u32 {
+        match self
+            .materials
+            .iter()
+            .rposition(|known| Arc::ptr_eq(known, material))
+        {
+            Some(index) => index as u32,
+            None => {
+                self.materials.push(Arc::clone(material));
+                (self.materials.len() - 1) as u32
             }
         }
     }
 
-    /// Convert from World to Screen coordinate system
+    /// Project a world-space point onto the render target
     #[inline]
-    fn to_screen(&self, p: Point3<f32>) -> Point3<f32> {
-        let transformed = self.camera.get_view_projection_matrix().transform_point(&p);
-        Point3::<f32>::new(
-            ((transformed.x + 1.0) / 2.0) * (self.get_width() as f32),
-            ((transformed.y + 1.0) / 2.0) * (self.get_height() as f32),
-            transformed.z,
+    fn to_screen(&self, p: Point3<f32>) -> Point4<f32> {
+        self.camera
+            .to_screen(p, self.target_width, self.target_height)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mesh::mesh::Mesh, renderer_config::DepthEncoding, transformable::Transformable};
+
+    /// Count the pixels covered by a ground plane which extends behind the camera
+    fn ground_pixels(cull_mode: CullMode) -> usize {
+        let mut config = RendererConfig::default();
+        config.cull_mode = cull_mode;
+        config.shadows = false;
+        let mut renderer = Renderer::new(64, 48, config);
+        renderer
+            .get_camera_mut()
+            .set_position(&Point3::<f32>::new(6.0, 6.0, 5.0));
+        renderer.get_camera_mut().look_at(&Point3::<f32>::origin());
+
+        renderer.clear();
+        renderer.draw(&Mesh::plane(20.0));
+        renderer.finish();
+        renderer
+            .get_colour_buffer_raw()
+            .chunks(4)
+            .filter(|pixel| pixel[..3] != [0, 0, 0])
+            .count()
+    }
+
+    #[test]
+    fn depth_bias_applies_to_hierarchical_rejection() {
+        let mut config = RendererConfig::default();
+        config.set_depth_encoding(DepthEncoding::ReversedZ);
+        config.shadows = false;
+        let mut renderer = Renderer::new(64, 48, config);
+        renderer
+            .get_camera_mut()
+            .set_position(&Point3::<f32>::new(0.0, 0.0, 10.0));
+        renderer.get_camera_mut().look_at(&Point3::<f32>::origin());
+
+        // Draw a decal just behind a coplanar quad, biased far enough to be drawn over it
+        let plane = Mesh::plane(100.0);
+        let quad = |z: f32, colour: Rgba<u8>| {
+            plane.get_geometry().iter().map(move |tri| {
+                let mut tri = tri.clone();
+                for vertex in [&mut tri.a, &mut tri.b, &mut tri.c] {
+                    vertex.position.z = z;
+                }
+                tri.colour = colour;
+                tri
+            })
+        };
+        let material = Arc::new(Material::default());
+        renderer.clear();
+        for tri in quad(0.0, Rgba([255, 0, 0, 255])) {
+            renderer.triangle(&tri, &material);
+        }
+        renderer.set_depth_bias(DepthBias::new(1e5, 0.0));
+        for tri in quad(-0.001, Rgba([0, 255, 0, 255])) {
+            renderer.triangle(&tri, &material);
+        }
+        renderer.finish();
+
+        let pixels = renderer.get_colour_buffer_raw();
+        assert!(pixels.chunks(4).all(|pixel| pixel[1] > pixel[0]));
+    }
+
+    /// Triangles drawn with a single material
+    struct Surface(Vec<Triangle>, Arc<Material>);
+
+    impl Drawable for Surface {
+        fn draw(&self, renderer: &mut Renderer) {
+            for tri in &self.0 {
+                renderer.triangle(tri, &self.1);
+            }
+        }
+
+        fn draw_wireframe(&self, renderer: &mut Renderer) {
+            self.draw(renderer);
+        }
+    }
+
+    /// Get the fraction of light reaching a point under a square casting shadows with `material`
+    fn visibility_under(material: Material) -> f32 {
+        let mut renderer = Renderer::new(16, 16, RendererConfig::default());
+        *renderer.get_lights_mut() = vec![Light::directional(
+            -Vector3::<f32>::z(),
+            Vector3::<f32>::new(1.0, 1.0, 1.0),
+            1.0,
+        )];
+
+        let surface = Surface(Mesh::plane(2.0).get_geometry().clone(), Arc::new(material));
+        renderer.clear();
+        renderer.cast_shadows(&surface);
+
+        let shadow_map = renderer.shadow_maps[0].as_ref().unwrap();
+        shadow_map.visibility(
+            &Point3::<f32>::new(0.0, 0.0, -1.0),
+            &Vector3::<f32>::z(),
+            &Vector3::<f32>::z(),
         )
     }
+
+    #[test]
+    fn blended_materials_cast_no_shadows() {
+        assert_eq!(visibility_under(Material::default()), 0.0);
+
+        let mut material = Material::default();
+        material.blend_mode = BlendMode::Alpha;
+        assert_eq!(visibility_under(material), 1.0);
+    }
+
+    /// Render two planes and a line over the first in separate draws, with forward or deferred
+    /// shading
+    fn separate_draws(deferred_shading: bool) -> Vec<u8> {
+        let mut config = RendererConfig::default();
+        config.deferred_shading = deferred_shading;
+        config.shadows = false;
+        let mut renderer = Renderer::new(64, 48, config);
+        renderer
+            .get_camera_mut()
+            .set_position(&Point3::<f32>::new(0.0, 0.0, 10.0));
+        renderer.get_camera_mut().look_at(&Point3::<f32>::origin());
+
+        let mut plane = Mesh::plane(4.0);
+        renderer.clear();
+        renderer.draw(&plane);
+        renderer.set_line_style(LineStyle::new(3.0, true));
+        renderer.line(
+            Point3::<f32>::new(-1.5, 0.0, 0.01),
+            Point3::<f32>::new(1.5, 0.0, 0.01),
+            Rgba([255, 0, 0, 255]),
+        );
+        plane.translate(Vector3::<f32>::new(0.0, 2.5, 0.5));
+        renderer.draw(&plane);
+        renderer.finish();
+        renderer.get_colour_buffer_raw().clone()
+    }
+
+    #[test]
+    fn deferred_lighting_keeps_lines_drawn_between_draws() {
+        let deferred = separate_draws(true);
+        assert!(deferred
+            .chunks(4)
+            .any(|pixel| pixel[0] > 150 && pixel[1] < 60));
+        assert!(deferred == separate_draws(false));
+    }
+
+    #[test]
+    fn triangles_crossing_the_camera_plane_keep_their_winding() {
+        let front_faces = ground_pixels(CullMode::Back);
+        assert!(front_faces > 64 * 48 / 2);
+        assert_eq!(ground_pixels(CullMode::Front), 0);
+        assert_eq!(ground_pixels(CullMode::None), front_faces);
+    }
 }
@@ -1,25 +1,401 @@
 use image::Rgba;
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::{
+    light::AmbientLight, post_process::PostEffect, rasterizer::DepthRange, texture::FilterMode,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ShadingModel {
+    BlinnPhong,
+    PhysicallyBased,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    Sorted,
+    WeightedBlended,
+}
+
+/// Comparison of a sample's depth against the stored depth. Whether smaller or larger depths are
+/// nearer depends on the depth encoding
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DepthCompare {
+    Never,
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+    NotEqual,
+    Always,
+}
+
+impl DepthCompare {
+    /// Check whether a sample at `depth` passes against the `stored` depth
+    #[inline]
+    pub fn passes(&self, depth: f32, stored: f32) -> bool {
+        match self {
+            DepthCompare::Never => false,
+            DepthCompare::Less => depth < stored,
+            DepthCompare::LessEqual => depth <= stored,
+            DepthCompare::Equal => depth == stored,
+            DepthCompare::GreaterEqual => depth >= stored,
+            DepthCompare::Greater => depth > stored,
+            DepthCompare::NotEqual => depth != stored,
+            DepthCompare::Always => true,
+        }
+    }
+
+    /// Check whether every depth in the range `depth` fails against every depth in the range
+    /// `stored`, for rejecting blocks of the hierarchical depth buffer
+    #[inline]
+    pub fn rejects(&self, depth: &DepthRange, stored: &DepthRange) -> bool {
+        match self {
+            DepthCompare::Never => true,
+            DepthCompare::Less => depth.min >= stored.max,
+            DepthCompare::LessEqual => depth.min > stored.max,
+            DepthCompare::Equal => depth.max < stored.min || depth.min > stored.max,
+            DepthCompare::GreaterEqual => depth.max < stored.min,
+            DepthCompare::Greater => depth.max <= stored.min,
+            DepthCompare::NotEqual | DepthCompare::Always => false,
+        }
+    }
+}
+
+/// Mapping from view-space depth to the values stored in the depth buffer
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DepthEncoding {
+    Standard,
+    ReversedZ,
+    Linear,
+}
+
+impl DepthEncoding {
+    /// Get the sign of a change in stored depth which moves towards the camera
+    #[inline]
+    pub fn nearer(&self) -> f32 {
+        match self {
+            DepthEncoding::ReversedZ => 1.0,
+            DepthEncoding::Standard | DepthEncoding::Linear => -1.0,
+        }
+    }
+}
+
+/// Offset moving the depth of a primitive towards the camera, made up of a constant number of
+/// steps of depth resolution and a multiple of the change in depth per pixel
+#[derive(Clone, Copy, PartialEq)]
+pub struct DepthBias {
+    pub constant: f32,
+    pub slope: f32,
+}
+
+impl DepthBias {
+    /// Create a bias of `constant` steps of depth resolution plus `slope` times the depth slope
+    pub fn new(constant: f32, slope: f32) -> DepthBias {
+        DepthBias { constant, slope }
+    }
+
+    /// Create a bias which leaves depths unchanged
+    pub fn default() -> DepthBias {
+        DepthBias::new(0.0, 0.0)
+    }
+
+    /// Get the magnitude of the offset for a primitive whose largest depth magnitude is `depth`
+    /// and whose depth changes by at most `slope` per pixel
+    #[inline]
+    pub fn offset(&self, depth: f32, slope: f32) -> f32 {
+        self.constant * depth.abs() * f32::EPSILON + self.slope * slope.abs()
+    }
+}
+
+/// Appearance of lines, with the width and dash lengths measured in pixels
+#[derive(Clone, PartialEq)]
+pub struct LineStyle {
+    pub width: f32,
+    pub anti_aliased: bool,
+    pub dash_pattern: Vec<f32>,
+}
+
+impl LineStyle {
+    /// Create a solid line style `width` pixels wide
+    pub fn new(width: f32, anti_aliased: bool) -> LineStyle {
+        LineStyle {
+            width,
+            anti_aliased,
+            dash_pattern: Vec::new(),
+        }
+    }
+
+    /// Get a solid, aliased line one pixel wide
+    pub fn default() -> LineStyle {
+        LineStyle::new(1.0, false)
+    }
+
+    /// Use alternating lengths of drawn and skipped line, or a solid line if `pattern` is empty
+    pub fn dashed(mut self, pattern: &[f32]) -> LineStyle {
+        self.dash_pattern = pattern.to_vec();
+        self
+    }
+
+    /// Check whether the point at `distance` along a line is within a drawn dash
+    pub fn is_drawn(&self, distance: f32) -> bool {
+        let period: f32 = self.dash_pattern.iter().sum();
+        if period <= 0.0 {
+            return true;
+        }
+
+        let mut position = distance.rem_euclid(period);
+        for (index, length) in self.dash_pattern.iter().enumerate() {
+            if position < *length {
+                return index % 2 == 0;
+            }
+            position -= length;
+        }
+        false
+    }
+}
+
+/// Which edges of each mesh are drawn as lines
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Wireframe {
+    None,
+    AllEdges,
+    HiddenLine,
+    Overlay,
+}
+
+/// Outlines drawn along the features of meshes, for technical illustrations. Screen-space edges
+/// are found from jumps in depth and creases in normals reconstructed from depth
+#[derive(Clone, Copy, PartialEq)]
+pub struct FeatureEdges {
+    pub silhouettes: bool,
+    pub creases: bool,
+    pub boundaries: bool,
+    pub screen_space: bool,
+    pub crease_angle: f32,
+    pub depth_threshold: f32,
+    pub colour: Rgba<u8>,
+}
+
+impl FeatureEdges {
+    /// Get a selection drawing no feature edges
+    pub fn default() -> FeatureEdges {
+        FeatureEdges {
+            silhouettes: false,
+            creases: false,
+            boundaries: false,
+            screen_space: false,
+            crease_angle: 30.0_f32.to_radians(),
+            depth_threshold: 0.05,
+            colour: Rgba([0, 0, 0, 255]),
+        }
+    }
+
+    /// Draw the silhouettes, creases and boundaries of each mesh
+    pub fn outline() -> FeatureEdges {
+        FeatureEdges {
+            silhouettes: true,
+            creases: true,
+            boundaries: true,
+            ..FeatureEdges::default()
+        }
+    }
+
+    /// Check whether any edges are drawn from mesh geometry
+    #[inline]
+    pub fn has_mesh_edges(&self) -> bool {
+        self.silhouettes || self.creases || self.boundaries
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+}
+
+/// Order of the vertices of a front-facing triangle, as seen by the camera
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Winding {
+    Clockwise,
+    CounterClockwise,
+}
+
+/// Number of samples taken for each pixel when anti-aliasing
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SampleCount {
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AntiAliasing {
+    None,
+    Multisample(SampleCount),
+    Supersample(SampleCount),
+}
+
+impl AntiAliasing {
+    /// Get the positions of the coverage samples within a pixel, relative to its sample point
+    pub fn sample_offsets(&self) -> Vec<Vector2<f32>> {
+        // Standard multisample patterns, in sixteenths of a pixel
+        let pattern: &[(i8, i8)] = match self {
+            AntiAliasing::Multisample(SampleCount::Two) => &[(4, 4), (-4, -4)],
+            AntiAliasing::Multisample(SampleCount::Four) => &[(-2, -6), (6, -2), (-6, 2), (2, 6)],
+            AntiAliasing::Multisample(SampleCount::Eight) => &[
+                (1, -3),
+                (-1, 3),
+                (5, 1),
+                (-3, -5),
+                (-5, 5),
+                (-7, -1),
+                (3, 7),
+                (7, -7),
+            ],
+            AntiAliasing::Multisample(SampleCount::Sixteen) => &[
+                (1, 1),
+                (-1, -3),
+                (-3, 2),
+                (4, -1),
+                (-5, -2),
+                (2, 5),
+                (5, 3),
+                (3, -5),
+                (-2, 6),
+                (0, -7),
+                (-4, -6),
+                (-6, 4),
+                (-8, 0),
+                (7, -4),
+                (6, 7),
+                (-7, -8),
+            ],
+            AntiAliasing::None | AntiAliasing::Supersample(_) => &[(0, 0)],
+        };
+
+        pattern
+            .iter()
+            .map(|(x, y)| Vector2::<f32>::new(*x as f32, *y as f32) / 16.0)
+            .collect()
+    }
+
+    /// Get the horizontal and vertical scale of the render resolution over the output
+    pub fn supersample_factors(&self) -> (u32, u32) {
+        match self {
+            AntiAliasing::Supersample(SampleCount::Two) => (2, 1),
+            AntiAliasing::Supersample(SampleCount::Four) => (2, 2),
+            AntiAliasing::Supersample(SampleCount::Eight) => (4, 2),
+            AntiAliasing::Supersample(SampleCount::Sixteen) => (4, 4),
+            AntiAliasing::None | AntiAliasing::Multisample(_) => (1, 1),
+        }
+    }
+}
 
 pub struct RendererConfig {
-    pub wireframe: bool,
+    pub wireframe: Wireframe,
+    pub wireframe_colour: Option<Rgba<u8>>,
+    pub wireframe_bias: DepthBias,
+    pub feature_edges: FeatureEdges,
     pub clear_colour: Rgba<u8>,
     pub field_of_view: f32,
+    pub z_near: f32,
+    pub z_far: f32,
+    pub anti_aliasing: AntiAliasing,
+    pub shading_model: ShadingModel,
+    pub ambient_light: AmbientLight,
+    pub texture_filter: FilterMode,
+    pub transparency: Transparency,
+    depth_encoding: DepthEncoding,
+    pub depth_compare: DepthCompare,
+    pub depth_write: bool,
+    pub clear_depth: f32,
+    pub depth_pre_pass: bool,
+    pub g_buffer: bool,
+    pub deferred_shading: bool,
+    pub id_buffer: bool,
+    pub cull_mode: CullMode,
+    pub front_face: Winding,
+    pub shadows: bool,
+    pub shadow_map_size: u32,
+    pub shadow_bias: f32,
+    pub shadow_pcf_radius: u32,
+    pub shadow_centre: Point3<f32>,
+    pub shadow_extent: f32,
+    pub ssao: bool,
+    pub ssao_radius: f32,
+    pub ssao_samples: u32,
+    pub ssao_blur_radius: u32,
+    pub ssao_intensity: f32,
+    pub post_process: Vec<PostEffect>,
 }
 
 impl RendererConfig {
-    ///
+    /// Get the config for shaded rendering
     pub fn default() -> RendererConfig {
         RendererConfig {
-            wireframe: false,
+            wireframe: Wireframe::None,
+            wireframe_colour: None,
+            wireframe_bias: DepthBias::new(4.0, 1.0),
+            feature_edges: FeatureEdges::default(),
             clear_colour: Rgba([0, 0, 0, 255]),
             field_of_view: std::f32::consts::PI / 2.0,
+            z_near: 0.1,
+            z_far: 10000.0,
+            anti_aliasing: AntiAliasing::None,
+            shading_model: ShadingModel::BlinnPhong,
+            ambient_light: AmbientLight::Constant(Vector3::<f32>::new(0.1, 0.1, 0.1)),
+            texture_filter: FilterMode::Trilinear,
+            transparency: Transparency::Sorted,
+            depth_encoding: DepthEncoding::ReversedZ,
+            depth_compare: DepthCompare::Greater,
+            depth_write: true,
+            clear_depth: 0.0,
+            depth_pre_pass: false,
+            g_buffer: false,
+            deferred_shading: false,
+            id_buffer: false,
+            cull_mode: CullMode::Back,
+            front_face: Winding::CounterClockwise,
+            shadows: true,
+            shadow_map_size: 1024,
+            shadow_bias: 0.05,
+            shadow_pcf_radius: 1,
+            shadow_centre: Point3::<f32>::origin(),
+            shadow_extent: 10.0,
+            ssao: false,
+            ssao_radius: 0.5,
+            ssao_samples: 16,
+            ssao_blur_radius: 2,
+            ssao_intensity: 1.0,
+            post_process: Vec::new(),
         }
     }
 
-    ///
+    /// Get the config for drawing only the edges of meshes
     pub fn default_wireframe() -> RendererConfig {
         let mut config = RendererConfig::default();
-        config.wireframe = true;
+        config.wireframe = Wireframe::AllEdges;
         config
     }
+
+    /// Get the mapping from view depth to the values written to the depth buffer
+    #[inline]
+    pub fn get_depth_encoding(&self) -> DepthEncoding {
+        self.depth_encoding
+    }
+
+    /// Select a depth encoding, along with the depth compare and clear depth which suit it. The
+    /// linear encoding clears to the far plane, so set `z_far` before calling this
+    pub fn set_depth_encoding(&mut self, depth_encoding: DepthEncoding) {
+        self.depth_encoding = depth_encoding;
+        (self.depth_compare, self.clear_depth) = match depth_encoding {
+            DepthEncoding::Standard => (DepthCompare::Less, 1.0),
+            DepthEncoding::ReversedZ => (DepthCompare::Greater, 0.0),
+            DepthEncoding::Linear => (DepthCompare::Less, self.z_far),
+        };
+    }
 }
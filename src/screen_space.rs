@@ -0,0 +1,229 @@
+use nalgebra::{Point3, Vector3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+
+use crate::{camera::Camera, renderer::DepthImage};
+
+/// World-space surfaces reconstructed from a depth image, with one entry per pixel
+pub struct ScreenSurfaces {
+    pub width: u32,
+    pub height: u32,
+    pub positions: Vec<Option<Point3<f32>>>,
+    pub depths: Vec<f32>,
+    pub normals: Vec<Option<Vector3<f32>>>,
+}
+
+impl ScreenSurfaces {
+    /// Reconstruct the position, view-space depth and normal of each pixel. Pixels at the clear
+    /// depth have no position or normal, and an infinite depth
+    pub fn reconstruct(camera: &Camera, depth_buffer: &DepthImage, clear_depth: f32) -> Self {
+        let (width, height) = depth_buffer.dimensions();
+
+        let positions: Vec<Option<Point3<f32>>> = depth_buffer
+            .as_raw()
+            .par_iter()
+            .enumerate()
+            .map(|(i, depth)| {
+                if *depth == clear_depth {
+                    return None;
+                }
+
+                let (x, y) = (i as u32 % width, i as u32 / width);
+                let view_depth = camera.linearize_depth(*depth);
+                Some(camera.to_world(x as f32 + 0.5, y as f32 + 0.5, view_depth, width, height))
+            })
+            .collect();
+        let depths: Vec<f32> = positions
+            .iter()
+            .map(|p| p.map_or(f32::INFINITY, |p| camera.view_depth(p)))
+            .collect();
+
+        let mut surfaces = ScreenSurfaces {
+            width,
+            height,
+            positions,
+            depths,
+            normals: Vec::new(),
+        };
+
+        // Reconstruct normals from the neighbours nearest in depth on each axis, so they don't
+        // reach across depth jumps
+        let normals: Vec<Option<Vector3<f32>>> = (0..surfaces.positions.len())
+            .into_par_iter()
+            .map(|i| {
+                let (positions, depths) = (&surfaces.positions, &surfaces.depths);
+                let p = positions[i]?;
+                let (x, y) = ((i as u32 % width) as i64, (i as u32 / width) as i64);
+                let tangent = |a: Option<usize>, b: Option<usize>| {
+                    let nearest = [a, b]
+                        .into_iter()
+                        .flatten()
+                        .filter(|j| positions[*j].is_some())
+                        .min_by(|j, k| {
+                            (depths[*j] - depths[i])
+                                .abs()
+                                .total_cmp(&(depths[*k] - depths[i]).abs())
+                        })?;
+                    let sign = if Some(nearest) == b { 1.0 } else { -1.0 };
+                    Some((positions[nearest]? - p) * sign)
+                };
+
+                let tangent_x = tangent(surfaces.index(x - 1, y), surfaces.index(x + 1, y))?;
+                let tangent_y = tangent(surfaces.index(x, y - 1), surfaces.index(x, y + 1))?;
+                tangent_x.cross(&tangent_y).try_normalize(f32::EPSILON)
+            })
+            .collect();
+        surfaces.normals = normals;
+
+        surfaces
+    }
+
+    /// Get the view-space depth at a screen-space position, or `None` if it is outside the
+    /// image. Reciprocal depth is affine across a plane, so it is interpolated between the
+    /// surrounding pixels unless one of them is across a jump in depth
+    pub fn depth_at(&self, x: f32, y: f32) -> Option<f32> {
+        let nearest = self.depths[self.index(x.floor() as i64, y.floor() as i64)?];
+
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (left, top) = (x.floor() as i64, y.floor() as i64);
+        let (tx, ty) = (x - x.floor(), y - y.floor());
+        let mut corners = [0.0; 4];
+        for (i, (dx, dy)) in [(0, 0), (1, 0), (0, 1), (1, 1)].into_iter().enumerate() {
+            let depth = match self.index(left + dx, top + dy) {
+                Some(j) => self.depths[j],
+                None => return Some(nearest),
+            };
+            if depth.is_infinite() || (depth - nearest).abs() > nearest * 0.05 {
+                return Some(nearest);
+            }
+            corners[i] = 1.0 / depth;
+        }
+
+        let upper = corners[0] + (corners[1] - corners[0]) * tx;
+        let lower = corners[2] + (corners[3] - corners[2]) * tx;
+        Some(1.0 / (upper + (lower - upper) * ty))
+    }
+
+    /// Get the index of a pixel, or `None` if it is outside the image
+    #[inline]
+    pub fn index(&self, x: i64, y: i64) -> Option<usize> {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            None
+        } else {
+            Some((y * self.width as i64 + x) as usize)
+        }
+    }
+}
+
+/// Estimate the fraction of ambient light reaching each pixel, by testing points in the
+/// hemisphere above its surface against the depth of the pixels they project onto
+pub fn ambient_occlusion(
+    surfaces: &ScreenSurfaces,
+    camera: &Camera,
+    radius: f32,
+    sample_count: u32,
+    intensity: f32,
+) -> Vec<f32> {
+    // Hemisphere kernel around +Z, with more samples close to the surface
+    let mut rng = StdRng::seed_from_u64(0);
+    let kernel: Vec<Vector3<f32>> = (0..sample_count)
+        .map(|i| {
+            let direction = Vector3::<f32>::new(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.05..1.0),
+            )
+            .normalize();
+            let scale = (i as f32 + 1.0) / sample_count as f32;
+            direction * rng.gen_range(0.0..1.0) * (0.1 + 0.9 * scale * scale)
+        })
+        .collect();
+
+    (0..surfaces.positions.len())
+        .into_par_iter()
+        .map(|i| {
+            let (position, normal) = match (surfaces.positions[i], surfaces.normals[i]) {
+                (Some(position), Some(normal)) => (position, normal),
+                _ => return 1.0,
+            };
+            let normal = if camera.is_facing(&position, &normal) {
+                normal
+            } else {
+                -normal
+            };
+
+            // Rotate the kernel around the normal by a different angle at each pixel, trading
+            // banding for a fine pattern which the blur removes
+            let (x, y) = (i as u32 % surfaces.width, i as u32 / surfaces.width);
+            let noise =
+                (52.982_918 * (0.067_110_56 * x as f32 + 0.005_837_15 * y as f32).fract()).fract();
+            let angle = noise * std::f32::consts::TAU;
+            let reference = if normal.x.abs() < 0.9 {
+                Vector3::<f32>::x()
+            } else {
+                Vector3::<f32>::y()
+            };
+            let u = normal.cross(&reference).normalize();
+            let v = normal.cross(&u);
+            let tangent = u * angle.cos() + v * angle.sin();
+            let bitangent = normal.cross(&tangent);
+            let depth = surfaces.depths[i];
+
+            let bias = radius * 0.025;
+
+            let mut occlusion = 0.0;
+            for offset in &kernel {
+                let sample = position
+                    + (tangent * offset.x + bitangent * offset.y + normal * offset.z) * radius;
+                let screen = camera.to_screen(sample, surfaces.width, surfaces.height);
+                let scene_depth = match surfaces.depth_at(screen.x, screen.y) {
+                    Some(scene_depth) => scene_depth,
+                    None => continue,
+                };
+
+                // Only surfaces near this one occlude it, fading out beyond the radius
+                if scene_depth <= camera.view_depth(sample) - bias {
+                    let range = (radius / (depth - scene_depth).abs()).clamp(0.0, 1.0);
+                    occlusion += range * range * (3.0 - 2.0 * range);
+                }
+            }
+
+            (1.0 - intensity * occlusion / sample_count.max(1) as f32).clamp(0.0, 1.0)
+        })
+        .collect()
+}
+
+/// Average each pixel's value with its neighbours up to `radius` pixels away on each axis, only
+/// including neighbours at a similar depth so the blur stops at depth jumps
+pub fn blur(values: &[f32], surfaces: &ScreenSurfaces, radius: u32) -> Vec<f32> {
+    let radius = radius as i64;
+    let pass = |values: &[f32], (dx, dy): (i64, i64)| -> Vec<f32> {
+        (0..values.len())
+            .into_par_iter()
+            .map(|i| {
+                let depth = surfaces.depths[i];
+                if depth.is_infinite() {
+                    return values[i];
+                }
+
+                let (x, y) = (
+                    (i as u32 % surfaces.width) as i64,
+                    (i as u32 / surfaces.width) as i64,
+                );
+                let (mut total, mut count) = (0.0, 0.0);
+                for step in -radius..=radius {
+                    if let Some(j) = surfaces.index(x + dx * step, y + dy * step) {
+                        if (surfaces.depths[j] - depth).abs() <= depth * 0.05 {
+                            total += values[j];
+                            count += 1.0;
+                        }
+                    }
+                }
+                total / count
+            })
+            .collect()
+    };
+
+    let horizontal = pass(values, (1, 0));
+    pass(&horizontal, (0, 1))
+}
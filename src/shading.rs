@@ -0,0 +1,214 @@
+use nalgebra::{Point3, Vector2, Vector3, Vector4};
+use std::f32::consts::PI;
+
+use crate::{
+    light::{AmbientLight, Light},
+    material::Material,
+    shadow_map::ShadowMap,
+    texture::FilterMode,
+};
+
+/// Interpolated surface attributes at a single pixel
+pub struct Fragment {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub tangent: Vector4<f32>,
+    pub colour: Vector3<f32>,
+    pub alpha: f32,
+    pub uv: Vector2<f32>,
+    pub uv_dx: Vector2<f32>,
+    pub uv_dy: Vector2<f32>,
+}
+
+/// Attributes of a surface at a single pixel which are needed to light it, either from an
+/// interpolated fragment or read back from the G-buffer
+pub struct Surface {
+    pub position: Point3<f32>,
+    pub normal: Vector3<f32>,
+    pub albedo: Vector3<f32>,
+}
+
+impl Fragment {
+    /// Evaluate the textures of a material to get the surface to be lit
+    pub fn surface(&self, material: &Material, filter: FilterMode) -> Surface {
+        Surface {
+            position: self.position,
+            normal: self.shading_normal(material, filter),
+            albedo: self.albedo(material, filter),
+        }
+    }
+
+    /// Get the base colour of a material at this fragment
+    pub fn albedo(&self, material: &Material, filter: FilterMode) -> Vector3<f32> {
+        let albedo = material.diffuse.component_mul(&self.colour);
+        match &material.diffuse_texture {
+            Some(texture) => {
+                let lod = texture.level_of_detail(&self.uv_dx, &self.uv_dy);
+                albedo.component_mul(&texture.sample(&self.uv, lod, filter).xyz())
+            }
+            None => albedo,
+        }
+    }
+
+    /// Get the opacity of a material at this fragment
+    pub fn opacity(&self, material: &Material, filter: FilterMode) -> f32 {
+        let alpha = self.alpha * material.dissolve;
+        match &material.diffuse_texture {
+            Some(texture) => {
+                let lod = texture.level_of_detail(&self.uv_dx, &self.uv_dy);
+                alpha * texture.sample(&self.uv, lod, filter).w
+            }
+            None => alpha,
+        }
+    }
+
+    /// Get the surface normal of a material at this fragment, perturbed by its normal map
+    pub fn shading_normal(&self, material: &Material, filter: FilterMode) -> Vector3<f32> {
+        let normal = self.normal.normalize();
+        let texture = match &material.normal_texture {
+            Some(texture) if self.tangent.xyz().norm_squared() > f32::EPSILON => texture,
+            _ => return normal,
+        };
+
+        // Build the tangent frame, reconstructing the bitangent from the handedness
+        let tangent = self.tangent.xyz();
+        let bitangent = normal.cross(&tangent) * self.tangent.w.signum();
+
+        let lod = texture.level_of_detail(&self.uv_dx, &self.uv_dy);
+        let sample = texture.sample(&self.uv, lod, filter).xyz() * 2.0 - Vector3::repeat(1.0);
+
+        (tangent * sample.x + bitangent * sample.y + normal * sample.z).normalize()
+    }
+}
+
+/// Signature shared by the shading models
+pub type ShadingFunction = fn(&Surface, &Material, &Lighting) -> Vector3<f32>;
+
+/// Light sources illuminating the fragments of a draw
+pub struct Lighting<'a> {
+    pub lights: &'a [Light],
+    pub shadow_maps: &'a [Option<ShadowMap>],
+    pub ambient_light: &'a AmbientLight,
+    pub eye: Point3<f32>,
+    pub texture_filter: FilterMode,
+}
+
+impl<'a> Lighting<'a> {
+    /// Get the direction and unoccluded radiance of each light facing a surface
+    pub fn incident<'b>(
+        &'b self,
+        position: &'b Point3<f32>,
+        normal: &'b Vector3<f32>,
+    ) -> impl Iterator<Item = (Vector3<f32>, Vector3<f32>)> + 'b {
+        self.lights
+            .iter()
+            .enumerate()
+            .filter_map(move |(index, light)| {
+                let (direction, radiance) = light.illuminate(position);
+                if normal.dot(&direction) <= 0.0 {
+                    return None;
+                }
+
+                let visibility = match self.shadow_maps.get(index) {
+                    Some(Some(shadow_map)) => shadow_map.visibility(position, normal, &direction),
+                    _ => 1.0,
+                };
+                if visibility <= 0.0 {
+                    return None;
+                }
+
+                Some((direction, radiance * visibility))
+            })
+    }
+}
+
+/// Shade a surface using the Blinn-Phong reflection model
+pub fn blinn_phong(surface: &Surface, material: &Material, lighting: &Lighting) -> Vector3<f32> {
+    let normal = surface.normal;
+    let view = (lighting.eye - surface.position).normalize();
+    let albedo = surface.albedo;
+
+    let mut colour = material.emissive
+        + material
+            .ambient
+            .component_mul(&lighting.ambient_light.irradiance(&normal))
+            .component_mul(&albedo)
+            * material.occlusion;
+
+    for (direction, radiance) in lighting.incident(&surface.position, &normal) {
+        let diffuse = normal.dot(&direction);
+        let halfway = (direction + view).normalize();
+        let specular = normal.dot(&halfway).max(0.0).powf(material.shininess);
+
+        colour += (albedo * diffuse + material.specular * specular).component_mul(&radiance);
+    }
+
+    colour
+}
+
+/// Shade a surface using a metallic/roughness Cook-Torrance GGX BRDF
+pub fn pbr(surface: &Surface, material: &Material, lighting: &Lighting) -> Vector3<f32> {
+    let normal = surface.normal;
+    let view = (lighting.eye - surface.position).normalize();
+    let base_colour = surface.albedo;
+    let roughness = material.roughness.clamp(0.04, 1.0);
+    let metallic = material.metallic.clamp(0.0, 1.0);
+    let n_dot_v = normal.dot(&view).max(1e-4);
+
+    // Dielectrics reflect ~4% at normal incidence, metals reflect their base colour
+    let f0 = Vector3::<f32>::repeat(0.04).lerp(&base_colour, metallic);
+    let diffuse_albedo = base_colour * (1.0 - metallic) / PI;
+
+    let mut colour = material.emissive;
+
+    for (direction, radiance) in lighting.incident(&surface.position, &normal) {
+        let n_dot_l = normal.dot(&direction);
+        let halfway = (direction + view).normalize();
+        let n_dot_h = normal.dot(&halfway).max(0.0);
+        let v_dot_h = view.dot(&halfway).max(0.0);
+
+        let d = distribution_ggx(n_dot_h, roughness);
+        let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+        let f = fresnel_schlick(v_dot_h, &f0);
+
+        let specular = f * (d * g / (4.0 * n_dot_v * n_dot_l));
+        let diffuse = (Vector3::<f32>::repeat(1.0) - f).component_mul(&diffuse_albedo);
+
+        colour += (diffuse + specular).component_mul(&radiance) * n_dot_l;
+    }
+
+    // Ambient term, split between diffuse irradiance and a roughness-blurred reflection
+    let f = fresnel_schlick(n_dot_v, &f0);
+    let reflection = (2.0 * n_dot_v * normal - view).normalize();
+    let ambient_diffuse = (Vector3::<f32>::repeat(1.0) - f)
+        .component_mul(&base_colour)
+        .component_mul(&lighting.ambient_light.irradiance(&normal))
+        * (1.0 - metallic);
+    let ambient_specular =
+        f.component_mul(&lighting.ambient_light.reflection(&reflection, roughness));
+
+    colour + (ambient_diffuse + ambient_specular) * material.occlusion
+}
+
+/// Trowbridge-Reitz GGX normal distribution function
+#[inline]
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let alpha_squared = roughness.powi(4);
+    let denominator = n_dot_h * n_dot_h * (alpha_squared - 1.0) + 1.0;
+    alpha_squared / (PI * denominator * denominator)
+}
+
+/// Smith geometry term using the Schlick-GGX approximation for direct lighting
+#[inline]
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = (roughness + 1.0).powi(2) / 8.0;
+    let g_v = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    g_v * g_l
+}
+
+/// Schlick's approximation of the Fresnel reflectance
+#[inline]
+fn fresnel_schlick(cos_theta: f32, f0: &Vector3<f32>) -> Vector3<f32> {
+    f0 + (Vector3::<f32>::repeat(1.0) - f0) * (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
@@ -0,0 +1,191 @@
+use image::Luma;
+use nalgebra::{Point3, Vector2, Vector3};
+
+use crate::{
+    camera::Camera,
+    light::Light,
+    mesh::triangle::Triangle,
+    rasterizer::{self, DepthTarget, RasterScratch},
+    renderer::DepthImage,
+    renderer_config::{DepthCompare, RendererConfig},
+};
+
+/// Depth of the scene as seen from a light, used to find occluded fragments
+pub struct ShadowMap {
+    camera: Camera,
+    size: u32,
+    depth_buffer: DepthImage,
+    bias: f32,
+    pcf_radius: i32,
+    scratch: RasterScratch,
+}
+
+impl ShadowMap {
+    /// Create an empty shadow map for a light, if the light type supports shadows
+    pub fn new(light: &Light, config: &RendererConfig) -> Option<ShadowMap> {
+        let extent = config.shadow_extent;
+
+        let camera = match light {
+            Light::Directional { direction, .. } => {
+                // Cover a sphere around the shadow centre with an orthographic projection
+                let mut camera =
+                    Camera::new_orthographic(2.0 * extent, 2.0 * extent, 0.0, 4.0 * extent);
+                camera.set_position(&(config.shadow_centre - direction * (2.0 * extent)));
+                camera.look_at(&config.shadow_centre);
+                camera
+            }
+            Light::Spot {
+                position,
+                direction,
+                outer_angle,
+                ..
+            } => {
+                let mut camera = Camera::new(1.0, 2.0 * outer_angle, 0.1, 4.0 * extent);
+                camera.set_position(position);
+                camera.look_at(&(position + direction));
+                camera
+            }
+            Light::Point { .. } => return None,
+        };
+
+        let size = config.shadow_map_size;
+        Some(ShadowMap {
+            camera,
+            size,
+            depth_buffer: DepthImage::from_pixel(size, size, Luma([f32::NEG_INFINITY])),
+            bias: config.shadow_bias,
+            pcf_radius: config.shadow_pcf_radius as i32,
+            scratch: RasterScratch::default(),
+        })
+    }
+
+    /// Get the camera which renders the map from the point of view of the light
+    #[inline]
+    pub fn get_camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// Get the width and height of the map in texels
+    #[inline]
+    pub fn get_size(&self) -> u32 {
+        self.size
+    }
+
+    /// Get the fraction of the light reaching a surface, filtered over the PCF kernel
+    pub fn visibility(
+        &self,
+        position: &Point3<f32>,
+        normal: &Vector3<f32>,
+        to_light: &Vector3<f32>,
+    ) -> f32 {
+        // Push the sample towards the light, more so at grazing angles
+        let slope = 1.0 - normal.dot(to_light).clamp(0.0, 1.0);
+        let position = position + to_light * self.bias + normal * (self.bias * slope);
+
+        let p = self.camera.to_screen(position, self.size, self.size);
+        let (x, y) = (p.x.floor() as i32, p.y.floor() as i32);
+
+        let mut lit = 0;
+        let mut total = 0;
+        for dy in -self.pcf_radius..=self.pcf_radius {
+            for dx in -self.pcf_radius..=self.pcf_radius {
+                let (u, v) = (x + dx, y + dy);
+                total += 1;
+
+                // Everything outside of the shadow map is lit
+                if u < 0 || v < 0 || u >= self.size as i32 || v >= self.size as i32 {
+                    lit += 1;
+                    continue;
+                }
+
+                if p.z >= self.depth_buffer.get_pixel(u as u32, v as u32)[0] {
+                    lit += 1;
+                }
+            }
+        }
+
+        lit as f32 / total as f32
+    }
+
+    /// Render the depth of a triangle into the shadow map
+    pub fn triangle(&mut self, tri: &Triangle) {
+        // Screen-space is only defined between the near and far planes
+        let vertices = [&tri.a, &tri.b, &tri.c];
+        if vertices
+            .iter()
+            .all(|v| self.camera.is_within_depth_range(&v.position))
+        {
+            self.rasterize_triangle(tri);
+        } else {
+            for part in self.camera.clip_triangle(tri) {
+                self.rasterize_triangle(&part);
+            }
+        }
+    }
+
+    /// Render the depth of a triangle between the near and far planes into the shadow map
+    fn rasterize_triangle(&mut self, tri: &Triangle) {
+        let points = [&tri.a, &tri.b, &tri.c]
+            .map(|v| self.camera.to_screen(v.position, self.size, self.size));
+        let mut target = DepthTarget {
+            depth_buffer: &mut self.depth_buffer,
+            hierarchical_depth: None,
+            width: self.size,
+            rows: 0..self.size,
+            reciprocal_depth: false,
+            scratch: std::mem::replace(&mut self.scratch, RasterScratch::default()),
+        };
+        rasterizer::rasterize(
+            &mut target,
+            &points,
+            &[Vector2::<f32>::zeros()],
+            DepthCompare::Greater,
+            true,
+            0.0,
+            |_, _, _, _| {},
+        );
+        self.scratch = target.scratch;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex::Vertex;
+    use image::Rgba;
+
+    #[test]
+    fn triangles_behind_a_spot_light_are_clipped() {
+        let mut config = RendererConfig::default();
+        config.shadow_map_size = 64;
+        let light = Light::spot(
+            Point3::<f32>::origin(),
+            -Vector3::<f32>::z(),
+            0.5,
+            0.6,
+            Vector3::<f32>::new(1.0, 1.0, 1.0),
+            1.0,
+        );
+        let mut shadow_map = ShadowMap::new(&light, &config).unwrap();
+
+        // A floor below the light which extends behind it only covers one half of the map
+        let vertex = |x: f32, z: f32| Vertex {
+            position: Point3::<f32>::new(x, -1.0, z),
+            normal: None,
+            tangent: None,
+            uv: None,
+            colour: None,
+        };
+        shadow_map.triangle(&Triangle {
+            a: vertex(-5.0, 5.0),
+            b: vertex(5.0, 5.0),
+            c: vertex(0.0, -5.0),
+            colour: Rgba([255, 255, 255, 255]),
+            material: 0,
+        });
+
+        let written = |y: u32| shadow_map.depth_buffer.get_pixel(32, y)[0] > f32::NEG_INFINITY;
+        assert!((32..64).any(written));
+        assert!(!(0..32).any(written));
+    }
+}
@@ -0,0 +1,257 @@
+use nalgebra::Vector2;
+use std::path::Path;
+
+/// Columns of the built-in 5x8 glyphs for printable ASCII, with the top row in the lowest bit
+const BITMAP_GLYPHS: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x00, 0x00, 0x5F, 0x00, 0x00],
+    [0x00, 0x07, 0x00, 0x07, 0x00],
+    [0x14, 0x7F, 0x14, 0x7F, 0x14],
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12],
+    [0x23, 0x13, 0x08, 0x64, 0x62],
+    [0x36, 0x49, 0x56, 0x20, 0x50],
+    [0x00, 0x08, 0x07, 0x03, 0x00],
+    [0x00, 0x1C, 0x22, 0x41, 0x00],
+    [0x00, 0x41, 0x22, 0x1C, 0x00],
+    [0x2A, 0x1C, 0x7F, 0x1C, 0x2A],
+    [0x08, 0x08, 0x3E, 0x08, 0x08],
+    [0x00, 0x80, 0x70, 0x30, 0x00],
+    [0x08, 0x08, 0x08, 0x08, 0x08],
+    [0x00, 0x00, 0x60, 0x60, 0x00],
+    [0x20, 0x10, 0x08, 0x04, 0x02],
+    [0x3E, 0x51, 0x49, 0x45, 0x3E],
+    [0x00, 0x42, 0x7F, 0x40, 0x00],
+    [0x72, 0x49, 0x49, 0x49, 0x46],
+    [0x21, 0x41, 0x49, 0x4D, 0x33],
+    [0x18, 0x14, 0x12, 0x7F, 0x10],
+    [0x27, 0x45, 0x45, 0x45, 0x39],
+    [0x3C, 0x4A, 0x49, 0x49, 0x31],
+    [0x41, 0x21, 0x11, 0x09, 0x07],
+    [0x36, 0x49, 0x49, 0x49, 0x36],
+    [0x46, 0x49, 0x49, 0x29, 0x1E],
+    [0x00, 0x00, 0x14, 0x00, 0x00],
+    [0x00, 0x40, 0x34, 0x00, 0x00],
+    [0x00, 0x08, 0x14, 0x22, 0x41],
+    [0x14, 0x14, 0x14, 0x14, 0x14],
+    [0x00, 0x41, 0x22, 0x14, 0x08],
+    [0x02, 0x01, 0x59, 0x09, 0x06],
+    [0x3E, 0x41, 0x5D, 0x59, 0x4E],
+    [0x7C, 0x12, 0x11, 0x12, 0x7C],
+    [0x7F, 0x49, 0x49, 0x49, 0x36],
+    [0x3E, 0x41, 0x41, 0x41, 0x22],
+    [0x7F, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x49, 0x49, 0x49, 0x41],
+    [0x7F, 0x09, 0x09, 0x09, 0x01],
+    [0x3E, 0x41, 0x41, 0x51, 0x73],
+    [0x7F, 0x08, 0x08, 0x08, 0x7F],
+    [0x00, 0x41, 0x7F, 0x41, 0x00],
+    [0x20, 0x40, 0x41, 0x3F, 0x01],
+    [0x7F, 0x08, 0x14, 0x22, 0x41],
+    [0x7F, 0x40, 0x40, 0x40, 0x40],
+    [0x7F, 0x02, 0x1C, 0x02, 0x7F],
+    [0x7F, 0x04, 0x08, 0x10, 0x7F],
+    [0x3E, 0x41, 0x41, 0x41, 0x3E],
+    [0x7F, 0x09, 0x09, 0x09, 0x06],
+    [0x3E, 0x41, 0x51, 0x21, 0x5E],
+    [0x7F, 0x09, 0x19, 0x29, 0x46],
+    [0x26, 0x49, 0x49, 0x49, 0x32],
+    [0x03, 0x01, 0x7F, 0x01, 0x03],
+    [0x3F, 0x40, 0x40, 0x40, 0x3F],
+    [0x1F, 0x20, 0x40, 0x20, 0x1F],
+    [0x3F, 0x40, 0x38, 0x40, 0x3F],
+    [0x63, 0x14, 0x08, 0x14, 0x63],
+    [0x03, 0x04, 0x78, 0x04, 0x03],
+    [0x61, 0x59, 0x49, 0x4D, 0x43],
+    [0x00, 0x7F, 0x41, 0x41, 0x41],
+    [0x02, 0x04, 0x08, 0x10, 0x20],
+    [0x00, 0x41, 0x41, 0x41, 0x7F],
+    [0x04, 0x02, 0x01, 0x02, 0x04],
+    [0x40, 0x40, 0x40, 0x40, 0x40],
+    [0x00, 0x03, 0x07, 0x08, 0x00],
+    [0x20, 0x54, 0x54, 0x78, 0x40],
+    [0x7F, 0x28, 0x44, 0x44, 0x38],
+    [0x38, 0x44, 0x44, 0x44, 0x28],
+    [0x38, 0x44, 0x44, 0x28, 0x7F],
+    [0x38, 0x54, 0x54, 0x54, 0x18],
+    [0x00, 0x08, 0x7E, 0x09, 0x02],
+    [0x18, 0xA4, 0xA4, 0x9C, 0x78],
+    [0x7F, 0x08, 0x04, 0x04, 0x78],
+    [0x00, 0x44, 0x7D, 0x40, 0x00],
+    [0x20, 0x40, 0x40, 0x3D, 0x00],
+    [0x7F, 0x10, 0x28, 0x44, 0x00],
+    [0x00, 0x41, 0x7F, 0x40, 0x00],
+    [0x7C, 0x04, 0x78, 0x04, 0x78],
+    [0x7C, 0x08, 0x04, 0x04, 0x78],
+    [0x38, 0x44, 0x44, 0x44, 0x38],
+    [0xFC, 0x18, 0x24, 0x24, 0x18],
+    [0x18, 0x24, 0x24, 0x18, 0xFC],
+    [0x7C, 0x08, 0x04, 0x04, 0x08],
+    [0x48, 0x54, 0x54, 0x54, 0x24],
+    [0x04, 0x04, 0x3F, 0x44, 0x24],
+    [0x3C, 0x40, 0x40, 0x20, 0x7C],
+    [0x1C, 0x20, 0x40, 0x20, 0x1C],
+    [0x3C, 0x40, 0x30, 0x40, 0x3C],
+    [0x44, 0x28, 0x10, 0x28, 0x44],
+    [0x4C, 0x90, 0x90, 0x90, 0x7C],
+    [0x44, 0x64, 0x54, 0x4C, 0x44],
+    [0x00, 0x08, 0x36, 0x41, 0x00],
+    [0x00, 0x00, 0x77, 0x00, 0x00],
+    [0x00, 0x41, 0x36, 0x08, 0x00],
+    [0x02, 0x01, 0x02, 0x04, 0x02],
+];
+
+/// Size of a built-in glyph cell in unscaled pixels, including the spacing to the next cell
+const BITMAP_ADVANCE: u32 = 6;
+const BITMAP_LINE_HEIGHT: u32 = 9;
+
+enum FontData {
+    Bitmap { scale: u32 },
+    TrueType { font: Box<fontdue::Font>, size: f32 },
+}
+
+/// A typeface at a fixed size, either the built-in bitmap font or a loaded TrueType font
+pub struct Font {
+    data: FontData,
+}
+
+impl Font {
+    /// Get the built-in 5x8 font, with each pixel scaled to `scale` pixels across
+    pub fn bitmap(scale: u32) -> Font {
+        Font {
+            data: FontData::Bitmap {
+                scale: scale.max(1),
+            },
+        }
+    }
+
+    /// Get the built-in bitmap font at its natural size
+    pub fn default() -> Font {
+        Font::bitmap(1)
+    }
+
+    /// Load a TrueType or OpenType font, rendered with the height of an em of `size` pixels
+    pub fn load_ttf<P: AsRef<Path>>(filepath: P, size: f32) -> Result<Font, String> {
+        let filepath = filepath.as_ref();
+        let bytes = std::fs::read(filepath)
+            .map_err(|e| format!("Failed to read {}: {}", filepath.display(), e))?;
+        let font = fontdue::Font::from_bytes(bytes, fontdue::FontSettings::default())
+            .map_err(|e| format!("Failed to parse {}: {}", filepath.display(), e))?;
+
+        Ok(Font {
+            data: FontData::TrueType {
+                font: Box::new(font),
+                size,
+            },
+        })
+    }
+
+    /// Get the distance between the tops of consecutive lines, in pixels
+    pub fn get_line_height(&self) -> f32 {
+        match &self.data {
+            FontData::Bitmap { scale } => (BITMAP_LINE_HEIGHT * scale) as f32,
+            FontData::TrueType { font, size } => font
+                .horizontal_line_metrics(*size)
+                .map_or(*size, |metrics| metrics.new_line_size.ceil()),
+        }
+    }
+
+    /// Get the width of the longest line and the total height of some text, in pixels
+    pub fn measure(&self, text: &str) -> Vector2<f32> {
+        let mut width = 0.0_f32;
+        let mut lines = 0;
+        for line in text.lines() {
+            width = width.max(self.layout_line(line, |_, _, _| {}));
+            lines += 1;
+        }
+        Vector2::<f32>::new(width, lines as f32 * self.get_line_height())
+    }
+
+    /// Rasterize some text with its top-left corner at the origin, calling `fragment` with the
+    /// position and coverage of each pixel it touches. Lines are separated by `\n`
+    pub fn rasterize<F>(&self, text: &str, mut fragment: F)
+    where
+        F: FnMut(i32, i32, f32),
+    {
+        let line_height = self.get_line_height();
+        for (index, line) in text.lines().enumerate() {
+            let top = (index as f32 * line_height) as i32;
+            self.layout_line(line, |x, y, coverage| fragment(x, top + y, coverage));
+        }
+    }
+
+    /// Rasterize a single line of text relative to the top-left of its line, returning its width
+    fn layout_line<F>(&self, line: &str, mut fragment: F) -> f32
+    where
+        F: FnMut(i32, i32, f32),
+    {
+        match &self.data {
+            FontData::Bitmap { scale } => {
+                let scale = *scale as i32;
+                let mut pen = 0;
+                for character in line.chars() {
+                    // Characters outside printable ASCII are drawn as question marks
+                    let code = character as u32;
+                    let glyph = if (0x20..0x7F).contains(&code) {
+                        &BITMAP_GLYPHS[(code - 0x20) as usize]
+                    } else {
+                        &BITMAP_GLYPHS[(b'?' - 0x20) as usize]
+                    };
+
+                    for (column, bits) in glyph.iter().enumerate() {
+                        for row in 0..8 {
+                            if bits & (1 << row) == 0 {
+                                continue;
+                            }
+                            for dy in 0..scale {
+                                for dx in 0..scale {
+                                    fragment(
+                                        pen + column as i32 * scale + dx,
+                                        row * scale + dy,
+                                        1.0,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                    pen += BITMAP_ADVANCE as i32 * scale;
+                }
+
+                // The spacing after the last glyph isn't part of the text
+                (pen - scale).max(0) as f32
+            }
+            FontData::TrueType { font, size } => {
+                let ascent = font
+                    .horizontal_line_metrics(*size)
+                    .map_or(*size, |metrics| metrics.ascent);
+                let mut pen = 0.0_f32;
+                let mut previous = None;
+
+                for character in line.chars() {
+                    if let Some(previous) = previous {
+                        pen += font
+                            .horizontal_kern(previous, character, *size)
+                            .unwrap_or(0.0);
+                    }
+                    previous = Some(character);
+
+                    // Glyph bitmaps are placed relative to the baseline, with y up
+                    let (metrics, coverage) = font.rasterize(character, *size);
+                    let left = pen.round() as i32 + metrics.xmin;
+                    let top = (ascent.round() as i32) - metrics.ymin - metrics.height as i32;
+                    for (index, value) in coverage.iter().enumerate() {
+                        if *value > 0 {
+                            fragment(
+                                left + (index % metrics.width) as i32,
+                                top + (index / metrics.width) as i32,
+                                *value as f32 / 255.0,
+                            );
+                        }
+                    }
+                    pen += metrics.advance_width;
+                }
+
+                pen.ceil()
+            }
+        }
+    }
+}
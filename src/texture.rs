@@ -0,0 +1,208 @@
+use image::RgbaImage;
+use nalgebra::{Vector2, Vector4};
+
+use crate::utilities;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+/// Encoding of the colour channels of an image. Colour maps are usually sRGB, while data such as
+/// normals must be read as stored
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColourSpace {
+    Srgb,
+    Linear,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FilterMode {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+/// A single level of a mipmap chain
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Vector4<f32>>,
+}
+
+pub struct Texture {
+    levels: Vec<MipLevel>,
+    pub wrap: WrapMode,
+}
+
+impl Texture {
+    /// Load an image as a mipmapped texture, decoding sRGB images to linear values
+    pub fn load(filepath: &str, colour_space: ColourSpace) -> Result<Texture, String> {
+        let image = image::open(filepath)
+            .map_err(|e| format!("Failed to load {}: {}", filepath, e))?
+            .to_rgba8();
+        Ok(Texture::from_image(&image, colour_space))
+    }
+
+    /// Create a texture from an image, decoding sRGB colours to linear so that filtering and
+    /// shading work in linear light. Alpha is always linear
+    pub fn from_image(image: &RgbaImage, colour_space: ColourSpace) -> Texture {
+        let decode = |value: u8| match colour_space {
+            ColourSpace::Srgb => utilities::srgb_to_linear(value as f32 / 255.0),
+            ColourSpace::Linear => value as f32 / 255.0,
+        };
+        let base = MipLevel {
+            width: image.width(),
+            height: image.height(),
+            texels: image
+                .pixels()
+                .map(|p| {
+                    Vector4::<f32>::new(
+                        decode(p[0]),
+                        decode(p[1]),
+                        decode(p[2]),
+                        p[3] as f32 / 255.0,
+                    )
+                })
+                .collect(),
+        };
+
+        // Build the mipmap chain by repeatedly halving the previous level
+        let mut levels = vec![base];
+        loop {
+            let previous = levels.last().unwrap();
+            if previous.width == 1 && previous.height == 1 {
+                break;
+            }
+            let next = previous.downsample();
+            levels.push(next);
+        }
+
+        Texture {
+            levels,
+            wrap: WrapMode::Repeat,
+        }
+    }
+
+    /// Get the width of the base level in texels
+    #[inline]
+    pub fn get_width(&self) -> u32 {
+        self.levels[0].width
+    }
+
+    /// Get the height of the base level in texels
+    #[inline]
+    pub fn get_height(&self) -> u32 {
+        self.levels[0].height
+    }
+
+    /// Select a mipmap level from the screen-space derivatives of the texture coordinates
+    pub fn level_of_detail(&self, uv_dx: &Vector2<f32>, uv_dy: &Vector2<f32>) -> f32 {
+        let size = Vector2::<f32>::new(self.get_width() as f32, self.get_height() as f32);
+        let footprint = uv_dx
+            .component_mul(&size)
+            .norm_squared()
+            .max(uv_dy.component_mul(&size).norm_squared());
+        (0.5 * footprint.max(f32::EPSILON).log2()).max(0.0)
+    }
+
+    /// Sample the texture at a texture coordinate, with `lod` selecting the mipmap level
+    pub fn sample(&self, uv: &Vector2<f32>, lod: f32, filter: FilterMode) -> Vector4<f32> {
+        match filter {
+            FilterMode::Nearest => self.sample_nearest(&self.levels[0], uv),
+            FilterMode::Bilinear => self.sample_bilinear(&self.levels[0], uv),
+            FilterMode::Trilinear => {
+                let max_level = (self.levels.len() - 1) as f32;
+                let lod = lod.min(max_level);
+                let lower = lod.floor();
+                let upper = lod.ceil();
+
+                let a = self.sample_bilinear(&self.levels[lower as usize], uv);
+                if upper == lower {
+                    return a;
+                }
+                let b = self.sample_bilinear(&self.levels[upper as usize], uv);
+                a.lerp(&b, lod - lower)
+            }
+        }
+    }
+
+    /// Sample the texel containing `uv`
+    fn sample_nearest(&self, level: &MipLevel, uv: &Vector2<f32>) -> Vector4<f32> {
+        // Texture coordinates have their origin at the bottom-left of the image
+        let x = (uv.x * level.width as f32).floor() as i32;
+        let y = ((1.0 - uv.y) * level.height as f32).floor() as i32;
+        level.get(self.wrap, x, y)
+    }
+
+    /// Blend the four texels nearest to `uv`
+    fn sample_bilinear(&self, level: &MipLevel, uv: &Vector2<f32>) -> Vector4<f32> {
+        // Offset by half a texel so that samples are centred on texels
+        let x = uv.x * level.width as f32 - 0.5;
+        let y = (1.0 - uv.y) * level.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let top = level
+            .get(self.wrap, x0, y0)
+            .lerp(&level.get(self.wrap, x0 + 1, y0), tx);
+        let bottom = level
+            .get(self.wrap, x0, y0 + 1)
+            .lerp(&level.get(self.wrap, x0 + 1, y0 + 1), tx);
+        top.lerp(&bottom, ty)
+    }
+}
+
+impl MipLevel {
+    /// Get a texel, resolving out of range coordinates with a wrap mode
+    #[inline]
+    fn get(&self, wrap: WrapMode, x: i32, y: i32) -> Vector4<f32> {
+        let x = wrap_coordinate(wrap, x, self.width as i32);
+        let y = wrap_coordinate(wrap, y, self.height as i32);
+        self.texels[(y as u32 * self.width + x as u32) as usize]
+    }
+
+    /// Create the next level of a mipmap chain using a box filter
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (x0, y0) = ((x * 2) as i32, (y * 2) as i32);
+                let sum = self.get(WrapMode::Clamp, x0, y0)
+                    + self.get(WrapMode::Clamp, x0 + 1, y0)
+                    + self.get(WrapMode::Clamp, x0, y0 + 1)
+                    + self.get(WrapMode::Clamp, x0 + 1, y0 + 1);
+                texels.push(sum / 4.0);
+            }
+        }
+
+        MipLevel {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+/// Map a texel coordinate into the range `0..size`
+#[inline]
+fn wrap_coordinate(wrap: WrapMode, t: i32, size: i32) -> i32 {
+    match wrap {
+        WrapMode::Repeat => t.rem_euclid(size),
+        WrapMode::Clamp => t.clamp(0, size - 1),
+        WrapMode::Mirror => {
+            let t = t.rem_euclid(2 * size);
+            if t < size {
+                t
+            } else {
+                2 * size - 1 - t
+            }
+        }
+    }
+}
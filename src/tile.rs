@@ -0,0 +1,339 @@
+use nalgebra::{Point3, Point4, Vector2, Vector3, Vector4};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::{
+    g_buffer::{GBufferSample, Hit, PrimitiveIds},
+    material::{BlendMode, Material},
+    mesh::triangle::Triangle,
+    rasterizer::{self, DepthTarget},
+    renderer_config::DepthCompare,
+    shading::{Fragment, Lighting, ShadingFunction, Surface},
+    utilities,
+};
+
+/// Number of rows in each tile of the screen
+pub const TILE_HEIGHT: u32 = 16;
+
+/// A triangle which has been projected to screen-space and is waiting to be rasterized
+pub struct ScreenTriangle {
+    points: [Point4<f32>; 3],
+    positions: [Point3<f32>; 3],
+    normals: [Vector3<f32>; 3],
+    tangents: [Vector4<f32>; 3],
+    uvs: [Vector2<f32>; 3],
+    colours: [Vector3<f32>; 3],
+    alphas: [f32; 3],
+    coverage_alpha: u8,
+    depth_bias: f32,
+    b_dx: Vector3<f32>,
+    b_dy: Vector3<f32>,
+    material: Arc<Material>,
+    weighted_blended: bool,
+    ids: PrimitiveIds,
+}
+
+impl ScreenTriangle {
+    /// Check whether the triangle hides what is behind it
+    #[inline]
+    pub fn is_opaque(&self) -> bool {
+        self.material.blend_mode == BlendMode::Opaque
+    }
+
+    /// Gather the shading attributes of a triangle with screen-space vertices `points`
+    pub fn new(
+        tri: &Triangle,
+        material: &Arc<Material>,
+        points: [Point4<f32>; 3],
+        face_normal: Vector3<f32>,
+        weighted_blended: bool,
+        depth_bias: f32,
+        ids: PrimitiveIds,
+    ) -> ScreenTriangle {
+        let tint = utilities::colour_to_vector(tri.colour);
+        let vertices = [&tri.a, &tri.b, &tri.c];
+
+        // Screen-space derivatives of the barycentric coordinates
+        let e1 = points[1].xy() - points[0].xy();
+        let e2 = points[2].xy() - points[0].xy();
+        let area = e1.perp(&e2);
+
+        ScreenTriangle {
+            points,
+            positions: vertices.map(|v| v.position),
+            normals: vertices.map(|v| v.normal.unwrap_or(face_normal)),
+            tangents: vertices.map(|v| v.tangent.unwrap_or_else(Vector4::<f32>::zeros)),
+            uvs: vertices.map(|v| v.uv.unwrap_or_else(Vector2::<f32>::zeros)),
+            colours: vertices.map(|v| match v.colour {
+                Some(colour) => utilities::colour_to_vector(colour).component_mul(&tint),
+                None => tint,
+            }),
+            alphas: vertices.map(|v| {
+                let alpha = v.colour.map_or(255, |colour| colour[3]) as f32 / 255.0;
+                alpha * (tri.colour[3] as f32 / 255.0)
+            }),
+            coverage_alpha: tri.colour[3],
+            depth_bias,
+            b_dx: Vector3::<f32>::new(e1.y - e2.y, e2.y, -e1.y) / area,
+            b_dy: Vector3::<f32>::new(e2.x - e1.x, -e2.x, e1.x) / area,
+            material: Arc::clone(material),
+            weighted_blended,
+            ids,
+        }
+    }
+
+    /// Get the range of rows which this triangle may cover, or `None` if it is off screen
+    pub fn rows(&self, height: u32) -> Option<Range<u32>> {
+        let min_y = self
+            .points
+            .iter()
+            .map(|p| p.y)
+            .fold(f32::INFINITY, f32::min);
+        let max_y = self
+            .points
+            .iter()
+            .map(|p| p.y)
+            .fold(f32::NEG_INFINITY, f32::max);
+
+        // Allow for samples offset into neighbouring pixels
+        let (min_y, max_y) = (min_y.floor() - 1.0, max_y.ceil() + 1.0);
+        if !(max_y >= 0.0 && min_y < height as f32) {
+            return None;
+        }
+
+        Some((min_y.max(0.0) as u32)..(max_y.min(height as f32) as u32))
+    }
+
+    /// Get the perspective-correct interpolation weights for screen-space barycentric coordinates
+    #[inline]
+    fn perspective(&self, b: Vector3<f32>) -> Vector3<f32> {
+        let w = Vector3::<f32>::new(
+            b.x * self.points[0].w,
+            b.y * self.points[1].w,
+            b.z * self.points[2].w,
+        );
+        w / (w.x + w.y + w.z)
+    }
+
+    /// Get the triangle and the position on it at screen-space barycentric coordinates
+    fn hit(&self, b: Vector3<f32>) -> Hit {
+        let w = self.perspective(b);
+        Hit {
+            ids: self.ids,
+            barycentrics: w,
+            position: Point3::<f32>::from(
+                self.positions[0].coords * w.x
+                    + self.positions[1].coords * w.y
+                    + self.positions[2].coords * w.z,
+            ),
+        }
+    }
+
+    /// Interpolate the texture coordinates at barycentric coordinates `w`
+    #[inline]
+    fn interpolate_uv(&self, w: Vector3<f32>) -> Vector2<f32> {
+        self.uvs[0] * w.x + self.uvs[1] * w.y + self.uvs[2] * w.z
+    }
+
+    /// Interpolate the shading attributes at screen-space barycentric coordinates
+    fn fragment(&self, b: Vector3<f32>) -> Fragment {
+        let w = self.perspective(b);
+        let uv = self.interpolate_uv(w);
+
+        Fragment {
+            position: Point3::<f32>::from(
+                self.positions[0].coords * w.x
+                    + self.positions[1].coords * w.y
+                    + self.positions[2].coords * w.z,
+            ),
+            normal: self.normals[0] * w.x + self.normals[1] * w.y + self.normals[2] * w.z,
+            tangent: self.tangents[0] * w.x + self.tangents[1] * w.y + self.tangents[2] * w.z,
+            colour: self.colours[0] * w.x + self.colours[1] * w.y + self.colours[2] * w.z,
+            alpha: self.alphas[0] * w.x + self.alphas[1] * w.y + self.alphas[2] * w.z,
+            uv,
+            uv_dx: self.interpolate_uv(self.perspective(b + self.b_dx)) - uv,
+            uv_dy: self.interpolate_uv(self.perspective(b + self.b_dy)) - uv,
+        }
+    }
+}
+
+/// A horizontal band of the screen, owning its rows of each sample buffer
+pub struct Tile<'a> {
+    pub depth: DepthTarget<'a>,
+    pub colour_buffer: &'a mut [f32],
+    pub accumulation_buffer: &'a mut [Vector4<f32>],
+    pub revealage_buffer: &'a mut [f32],
+    pub g_buffer: &'a mut [GBufferSample],
+    pub id_buffer: &'a mut [Option<Hit>],
+    pub deferred: bool,
+    pub unlit: &'a mut Vec<usize>,
+}
+
+impl Tile<'_> {
+    /// Render only the depth of a triangle within this tile, along with its IDs
+    pub fn depth(
+        &mut self,
+        triangle: &ScreenTriangle,
+        sample_offsets: &[Vector2<f32>],
+        depth_compare: DepthCompare,
+    ) {
+        let samples = sample_offsets.len() as u32;
+        let buffer_width = self.depth.width * samples;
+        let first_row = self.depth.rows.start;
+        let id_buffer = &mut *self.id_buffer;
+
+        rasterizer::rasterize(
+            &mut self.depth,
+            &triangle.points,
+            sample_offsets,
+            depth_compare,
+            true,
+            triangle.depth_bias,
+            |x, y, b, coverage| {
+                if id_buffer.is_empty() {
+                    return;
+                }
+
+                let hit = triangle.hit(b);
+                for sample in (0..samples).filter(|s| coverage & (1 << s) != 0) {
+                    let index = ((y - first_row) * buffer_width + x * samples + sample) as usize;
+                    id_buffer[index] = Some(hit);
+                }
+            },
+        );
+    }
+
+    /// Rasterize and shade the part of a triangle within this tile
+    pub fn triangle(
+        &mut self,
+        triangle: &ScreenTriangle,
+        sample_offsets: &[Vector2<f32>],
+        shade: ShadingFunction,
+        lighting: &Lighting,
+        depth_compare: DepthCompare,
+        depth_write: bool,
+    ) {
+        let material = &*triangle.material;
+        let blend_mode = material.blend_mode;
+        let samples = sample_offsets.len() as u32;
+        let buffer_width = self.depth.width * samples;
+        let first_row = self.depth.rows.start;
+
+        let colour_buffer = &mut *self.colour_buffer;
+        let accumulation_buffer = &mut *self.accumulation_buffer;
+        let revealage_buffer = &mut *self.revealage_buffer;
+        let g_buffer = &mut *self.g_buffer;
+        let id_buffer = &mut *self.id_buffer;
+        let unlit = &mut *self.unlit;
+        let coverage_alpha = triangle.coverage_alpha as f32 / 255.0;
+
+        // IDs are written wherever depth is
+        let write_ids = !id_buffer.is_empty() && depth_write && blend_mode == BlendMode::Opaque;
+
+        // Only opaque surfaces are recorded in the G-buffer, and lit from it when deferred
+        let write_g_buffer = !g_buffer.is_empty() && blend_mode == BlendMode::Opaque;
+        let deferred = self.deferred && write_g_buffer;
+
+        rasterizer::rasterize(
+            &mut self.depth,
+            &triangle.points,
+            sample_offsets,
+            depth_compare,
+            depth_write,
+            triangle.depth_bias,
+            |x, y, b, coverage| {
+                let fragment = triangle.fragment(b);
+                let surface = fragment.surface(material, lighting.texture_filter);
+                let hit = if write_ids {
+                    Some(triangle.hit(b))
+                } else {
+                    None
+                };
+
+                // Shade once per pixel, then write to each covered sample
+                let colour = if deferred {
+                    Vector3::<f32>::zeros()
+                } else {
+                    shade(&surface, material, lighting)
+                };
+                let alpha = match blend_mode {
+                    BlendMode::Opaque => 1.0,
+                    _ => fragment.opacity(material, lighting.texture_filter),
+                };
+
+                // Weight fragments by distance and opacity rather than sorting them
+                let weight = if triangle.weighted_blended {
+                    let distance = (fragment.position - lighting.eye).norm();
+                    alpha
+                        * utilities::clamp_f32(
+                            0.03 / (1e-5 + (distance / 200.0).powi(4)),
+                            1e-2,
+                            3e3,
+                        )
+                } else {
+                    0.0
+                };
+
+                for sample in (0..samples).filter(|s| coverage & (1 << s) != 0) {
+                    let index = ((y - first_row) * buffer_width + x * samples + sample) as usize;
+                    let pixel = &mut colour_buffer[index * 4..index * 4 + 4];
+
+                    if write_ids {
+                        id_buffer[index] = hit;
+                    }
+                    if write_g_buffer {
+                        g_buffer[index] = GBufferSample {
+                            position: surface.position,
+                            normal: surface.normal,
+                            albedo: surface.albedo,
+                            uv: fragment.uv,
+                            ids: triangle.ids,
+                        };
+                    }
+
+                    if deferred {
+                        // Only the alpha is known until the sample is lit
+                        pixel[3] = coverage_alpha;
+                        unlit.push(index);
+                    } else if blend_mode == BlendMode::Opaque {
+                        pixel.copy_from_slice(colour.push(coverage_alpha).as_slice());
+                    } else if triangle.weighted_blended {
+                        accumulation_buffer[index] += (colour * alpha).push(alpha) * weight;
+                        revealage_buffer[index] *= 1.0 - alpha;
+                    } else {
+                        let destination = Vector3::<f32>::new(pixel[0], pixel[1], pixel[2]);
+                        let blended = blend_mode.blend(&colour, alpha, &destination);
+                        pixel[..3].copy_from_slice(blended.as_slice());
+                    }
+                }
+            },
+        );
+    }
+
+    /// Light the samples whose G-buffer has been written since the last lighting pass, which runs
+    /// once the opaque geometry of a frame is drawn, so each sample is shaded once however many
+    /// triangles covered it
+    pub fn light(
+        &mut self,
+        materials: &[Arc<Material>],
+        shade: ShadingFunction,
+        lighting: &Lighting,
+    ) {
+        let mut unlit = std::mem::take(self.unlit);
+        unlit.sort_unstable();
+        unlit.dedup();
+
+        for index in unlit {
+            let sample = &self.g_buffer[index];
+            let surface = Surface {
+                position: sample.position,
+                normal: sample.normal,
+                albedo: sample.albedo,
+            };
+            let material = &materials[sample.ids.material as usize];
+            let colour = shade(&surface, material, lighting);
+
+            self.colour_buffer[index * 4..index * 4 + 3].copy_from_slice(colour.as_slice());
+        }
+    }
+}
@@ -1,12 +1,12 @@
 use nalgebra::Vector3;
 
 pub trait Transformable {
-    ///
+    /// Move the object by `delta`
     fn translate(&mut self, delta: Vector3<f32>);
 
-    ///
+    /// Rotate the object about the x, y and z axes by angles in radians
     fn rotate(&mut self, x: f32, y: f32, z: f32);
 
-    ///
+    /// Scale the object uniformly by `factor`
     fn scale(&mut self, factor: f32);
 }
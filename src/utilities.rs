@@ -3,13 +3,13 @@ use nalgebra::{Point3, Vector3};
 use rand::Rng;
 use std::cmp;
 
-///
+/// Restrict `t` to the range from `min` to `max`
 #[inline]
 pub fn clamp<T: cmp::Ord>(t: T, min: T, max: T) -> T {
     cmp::min(max, cmp::max(min, t))
 }
 
-///
+/// Restrict `t` to the range from `min` to `max`
 #[inline]
 pub fn clamp_f32(t: f32, min: f32, max: f32) -> f32 {
     if t < min {
@@ -21,13 +21,13 @@ pub fn clamp_f32(t: f32, min: f32, max: f32) -> f32 {
     }
 }
 
-///
+/// Map `value` smoothly onto the range from 0 to 1
 #[inline]
 pub fn sigmoid(value: f32) -> f32 {
     1.0 / (1.0 + (-value).exp())
 }
 
-///
+/// Get the barycentric coordinates of `p` in the triangle `p0`, `p1`, `p2`
 pub fn barycentric(
     p0: Point3<f32>,
     p1: Point3<f32>,
@@ -52,7 +52,48 @@ pub fn barycentric(
     Vector3::<f32>::new(u, v, w)
 }
 
-///
+/// Convert an 8-bit sRGB colour to a linear RGB vector
+#[inline]
+pub fn colour_to_vector(colour: Rgba<u8>) -> Vector3<f32> {
+    Vector3::<f32>::new(
+        srgb_to_linear(colour[0] as f32 / 255.0),
+        srgb_to_linear(colour[1] as f32 / 255.0),
+        srgb_to_linear(colour[2] as f32 / 255.0),
+    )
+}
+
+/// Convert a normalised RGB vector to an 8-bit colour
+#[inline]
+pub fn vector_to_colour(colour: Vector3<f32>, alpha: u8) -> Rgba<u8> {
+    Rgba([
+        (clamp_f32(colour.x, 0.0, 1.0) * 255.0).round() as u8,
+        (clamp_f32(colour.y, 0.0, 1.0) * 255.0).round() as u8,
+        (clamp_f32(colour.z, 0.0, 1.0) * 255.0).round() as u8,
+        alpha,
+    ])
+}
+
+/// Decode a colour channel encoded with the sRGB transfer function to linear light
+#[inline]
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear colour channel with the sRGB transfer function
+#[inline]
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value.max(0.0) * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Get a random opaque colour
 pub fn random_colour() -> Rgba<u8> {
     let mut rng = rand::thread_rng();
     let r = rng.gen_range(0..=255);